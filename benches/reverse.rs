@@ -0,0 +1,112 @@
+// Compares starting from a late literal (reverse suffix / reverse inner) with reading every line from the
+// start. Run with `cargo bench --bench reverse`.
+//
+// The forward numbers come from the same pattern with `|\xff` added: a raw byte that never occurs in the
+// generated text, so the matches are the same but no literal is required anymore and the lazy DFA has to
+// scan every line from its start.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use codecrafters_grep::Regex;
+
+
+const RUNS: u32 = 5;
+
+
+// Deterministic filler text, words of lowercase letters separated by spaces
+struct Words(u64);
+
+
+impl Words {
+    fn push_words(&mut self, text: &mut String, len: usize) {
+        let target = text.len() + len;
+        while text.len() < target {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let word_len = 2 + (self.0 >> 60) as usize;
+            text.extend((0..word_len).map(|ind| (b'a' + ((self.0 >> (ind * 5)) % 26) as u8) as char));
+            text.push(' ');
+        }
+    }
+}
+
+
+// One big file: 10 MB in a single line with the only email address at the very end
+fn long_line() -> String {
+    let mut text = String::new();
+    Words(1).push_words(&mut text, 10_000_000);
+    text.push_str("someone@example.com");
+    text
+}
+
+
+// 20k lines of 2 KB. Every line mentions example.com but only every 100th one has an address at it.
+fn log_lines() -> Vec<String> {
+    let mut words = Words(2);
+    (0..20_000)
+        .map(|line_index| {
+            let mut line = String::new();
+            words.push_words(&mut line, 2_000);
+            if line_index % 100 == 0 {
+                line.push_str("user@example.com");
+            } else {
+                line.push_str("see @example.com for details");
+            }
+            line
+        })
+        .collect()
+}
+
+
+// Lines like "1234 ERROR disk: ..." somewhere in the middle of 2 KB of text, in every 50th line
+fn error_lines() -> Vec<String> {
+    let mut words = Words(3);
+    (0..20_000)
+        .map(|line_index| {
+            let mut line = String::new();
+            words.push_words(&mut line, 1_000);
+            line.push_str(if line_index % 50 == 0 { "1234 ERROR disk: full " } else { "no ERROR here " });
+            words.push_words(&mut line, 1_000);
+            line
+        })
+        .collect()
+}
+
+
+fn time(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    (best, result)
+}
+
+
+fn compare(name: &str, pattern: &str, lines: &[String]) {
+    let reverse = Regex::new(pattern).unwrap();
+    let forward = Regex::new(&format!(r"(?:{})|\xff", pattern)).unwrap();
+
+    let (reverse_time, reverse_count) = time(|| lines.iter().filter(|line| reverse.is_match(line)).count());
+    let (forward_time, forward_count) = time(|| lines.iter().filter(|line| forward.is_match(line)).count());
+    assert_eq!(reverse_count, forward_count, "{}: both have to find the same lines", name);
+
+    println!(
+        "{:<12} {:<28} forward {:>9.2?}   reverse {:>9.2?}   {:>6.1}x   ({} matching lines)",
+        name,
+        pattern,
+        forward_time,
+        reverse_time,
+        forward_time.as_secs_f64() / reverse_time.as_secs_f64(),
+        reverse_count,
+    );
+}
+
+
+fn main() {
+    compare("long line", r"\w+@example\.com", &[long_line()]);
+    compare("log lines", r"\w+@example\.com", &log_lines());
+    compare("inner", r"\d+ ERROR \w+:", &error_lines());
+}
@@ -0,0 +1,187 @@
+use crate::error::Error;
+use crate::matcher::Budget;
+use crate::nfa::{Inst, Program};
+use crate::pikevm::Slots;
+use crate::utf8::decode;
+use crate::utils::{is_line_end, is_line_start, match_unit};
+
+
+// Most (pc, byte position) pairs the visited bitsets may cover, longer inputs go to solve() instead
+pub const MAX_VISITED: usize = 256 * 1024 * 8;
+
+
+// Work left on the explicit stack, so deep inputs can't overflow the Rust stack
+enum Job {
+    Explore(usize, usize),             // run pc at position
+    RestoreSlot(usize, Option<usize>), // undo a Save once everything after it failed
+    RestoreOpened(usize, Option<usize>),
+    LeavePath(usize, usize),           // (pc, position) is no longer on the current path
+}
+
+
+struct BitSet(Vec<u64>);
+
+
+impl BitSet {
+    fn new(len: usize) -> BitSet {
+        BitSet(vec![0; len.div_ceil(64)])
+    }
+
+    fn contains(&self, ind: usize) -> bool {
+        self.0[ind / 64] & (1 << (ind % 64)) != 0
+    }
+
+    fn insert(&mut self, ind: usize) {
+        self.0[ind / 64] |= 1 << (ind % 64);
+    }
+
+    fn remove(&mut self, ind: usize) {
+        self.0[ind / 64] &= !(1 << (ind % 64));
+    }
+}
+
+
+// Backtracks over the NFA program in priority order and returns the slots of the leftmost-first match.
+//
+// From a pc that can't reach a Backreference the outcome only depends on the position, so every such
+// (pc, position) pair is explored at most once over the whole search, start positions included. That
+// makes the backreference-free parts linear in program size * input length. The other pcs depend on
+// the captures and are explored again for every path, only cycles without progress are cut off.
+//
+// A group only gets its new span once it's closed, so a backreference inside the group it refers to
+// (like in (a\1?)+) sees what the previous repetition captured, the same as in solve().
+//
+// Every instruction run is a step of the budget. haystack.len() + 1 times the program length must
+// not be more than MAX_VISITED.
+pub fn search(
+    program: &Program,
+    haystack: &[u8],
+    start: usize,
+    anchored: bool,
+    budget: &mut Budget,
+) -> Result<Option<Slots>, Error> {
+    let positions = haystack.len() + 1;
+    let mut visited = BitSet::new(program.insts.len() * positions);
+    let mut on_path = BitSet::new(program.insts.len() * positions);
+    let mut slots = vec![None; program.slot_count];
+    let mut opened = vec![None; program.slot_count]; // start slot of every group that's open right now
+    let mut stack = vec![];
+
+    let mut start_pos = start;
+    loop {
+        stack.push(Job::Explore(0, start_pos));
+
+        while let Some(job) = stack.pop() {
+            let (pc, pos) = match job {
+                Job::Explore(pc, pos) => (pc, pos),
+                Job::RestoreSlot(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+                Job::RestoreOpened(slot, old) => {
+                    opened[slot] = old;
+                    continue;
+                }
+                Job::LeavePath(pc, pos) => {
+                    on_path.remove(pc * positions + pos);
+                    continue;
+                }
+            };
+
+            budget.step()?;
+
+            let key = pc * positions + pos;
+            if program.reaches_backreference[pc] {
+                // another path may have captured something else, only stop going round in circles
+                if on_path.contains(key) {
+                    continue;
+                }
+                on_path.insert(key);
+                stack.push(Job::LeavePath(pc, pos));
+            } else {
+                // this pair failed before and would fail the same way again
+                if visited.contains(key) {
+                    continue;
+                }
+                visited.insert(key);
+            }
+
+            match &program.insts[pc] {
+                Inst::Match(_) => return Ok(Some(slots)),
+
+                Inst::Char(node) => {
+                    if let Some((unit, len)) = decode(haystack, pos) {
+                        if match_unit(node, unit) {
+                            stack.push(Job::Explore(pc + 1, pos + len));
+                        }
+                    }
+                }
+
+                // the preferred branch goes on top so it's explored first
+                Inst::Split(preferred, other) => {
+                    stack.push(Job::Explore(*other, pos));
+                    stack.push(Job::Explore(*preferred, pos));
+                }
+
+                Inst::Jmp(target) => stack.push(Job::Explore(*target, pos)),
+
+                // start slots are even, end slots odd
+                Inst::Save(slot) if slot % 2 == 0 => {
+                    stack.push(Job::RestoreOpened(*slot, opened[*slot]));
+                    opened[*slot] = Some(pos);
+                    stack.push(Job::Explore(pc + 1, pos));
+                }
+
+                Inst::Save(slot) => {
+                    let start_slot = slot - 1;
+                    stack.push(Job::RestoreSlot(start_slot, slots[start_slot]));
+                    stack.push(Job::RestoreSlot(*slot, slots[*slot]));
+                    slots[start_slot] = opened[start_slot];
+                    slots[*slot] = Some(pos);
+                    stack.push(Job::Explore(pc + 1, pos));
+                }
+
+                Inst::StartOfLine => {
+                    if pos == 0 {
+                        stack.push(Job::Explore(pc + 1, pos));
+                    }
+                }
+
+                Inst::EndOfLine => {
+                    if pos == haystack.len() {
+                        stack.push(Job::Explore(pc + 1, pos));
+                    }
+                }
+
+                Inst::LineStart(terminator) => {
+                    if is_line_start(haystack, pos, *terminator) {
+                        stack.push(Job::Explore(pc + 1, pos));
+                    }
+                }
+
+                Inst::LineEnd(terminator) => {
+                    if is_line_end(haystack, pos, *terminator) {
+                        stack.push(Job::Explore(pc + 1, pos));
+                    }
+                }
+
+                Inst::Backreference(group_id) => {
+                    // a group that doesn't exist or didn't participate matches nothing, like in solve()
+                    let slot = |ind: usize| slots.get(ind).copied().flatten();
+                    if let (Some(group_start), Some(group_end)) = (slot(*group_id as usize * 2), slot(*group_id as usize * 2 + 1)) {
+                        let end = pos + (group_end - group_start);
+                        if end <= haystack.len() && haystack[pos..end] == haystack[group_start..group_end] {
+                            stack.push(Job::Explore(pc + 1, end));
+                        }
+                    }
+                }
+            }
+        }
+
+        // the next start is the next char, not the next byte
+        match decode(haystack, start_pos) {
+            Some((_, len)) if !anchored => start_pos += len,
+            _ => return Ok(None),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::nfa::{Inst, Program};
+use crate::utf8::{decode, decode_last, Unit};
+use crate::utils::match_unit;
+
+
+// Above this many states the whole cache is thrown away and rebuilt on demand
+const MAX_CACHED_STATES: usize = 4096;
+
+const UNKNOWN: usize = usize::MAX;
+
+
+// A DFA state is the set of NFA pcs the Pike VM would have threads on, minus the captures.
+// Only pcs that wait for something are kept: Char, EndOfLine and Match.
+struct State {
+    pcs: Vec<usize>,
+    matches: Vec<usize>, // the patterns of the Match pcs, see nfa::compile_set
+    ascii_transitions: [usize; 128], // UNKNOWN until the transition is first needed
+    other_transitions: HashMap<Unit, usize>, // non-ASCII chars and invalid bytes
+}
+
+
+// DFA states and transitions built lazily from the NFA program while searching
+pub struct DfaCache {
+    states: Vec<State>,
+    state_ids: HashMap<Vec<usize>, usize>,
+    start_pcs: Vec<usize>,   // closure of the start of the program at position 0
+    restart_pcs: Vec<usize>, // same but at any later position, added after every char for unanchored search
+    anchored: bool,          // only matches starting where the search starts, restart_pcs aren't added
+}
+
+
+impl fmt::Debug for DfaCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DfaCache({} states)", self.states.len())
+    }
+}
+
+
+impl DfaCache {
+    pub fn new(program: &Program) -> DfaCache {
+        DfaCache {
+            states: vec![],
+            state_ids: HashMap::new(),
+            start_pcs: closure(program, &[0], true, false),
+            restart_pcs: closure(program, &[0], false, false),
+            anchored: false,
+        }
+    }
+
+    // A cache for is_match_at, the states of unanchored searches have the restart pcs mixed in
+    pub fn anchored(program: &Program) -> DfaCache {
+        DfaCache { anchored: true, ..DfaCache::new(program) }
+    }
+
+    // Runs f on the shared cache, or on a fresh one made with `new` if another thread is using it (or
+    // panicked while holding it). Waiting for the lock would make threads searching in parallel take turns.
+    pub fn with_shared<T>(shared: &Mutex<DfaCache>, program: &Program, new: fn(&Program) -> DfaCache, f: impl FnOnce(&mut DfaCache) -> T) -> T {
+        match shared.try_lock() {
+            Ok(mut cache) => f(&mut cache),
+            Err(_) => f(&mut new(program)),
+        }
+    }
+
+    // Returns true if the program matches anywhere in the input. The program must not use backreferences.
+    pub fn is_match(&mut self, program: &Program, haystack: &[u8]) -> bool {
+        self.clear_if_full();
+        let start_pcs = self.start_pcs.clone();
+        let mut state = self.add_state(program, start_pcs);
+
+        let mut pos = 0;
+        while let Some((unit, len)) = decode(haystack, pos) {
+            pos += len;
+
+            if !self.states[state].matches.is_empty() {
+                return true;
+            }
+            // a dead state can never match again, happens for anchored patterns like ^abc
+            if self.states[state].pcs.is_empty() {
+                return false;
+            }
+
+            state = self.next_state(program, state, unit);
+        }
+
+        let state = &self.states[state];
+        !state.matches.is_empty() || !matches_at_end(program, &state.pcs, haystack.is_empty()).is_empty()
+    }
+
+    // Marks every pattern of a set program (see nfa::compile_set) that matches anywhere in the input.
+    // Unlike is_match this goes on after the first match, until every pattern has matched.
+    pub fn which_match(&mut self, program: &Program, haystack: &[u8], matched: &mut [bool]) {
+        self.clear_if_full();
+        let start_pcs = self.start_pcs.clone();
+        let mut state = self.add_state(program, start_pcs);
+
+        let mut pos = 0;
+        loop {
+            for &pattern_id in &self.states[state].matches {
+                matched[pattern_id] = true;
+            }
+            if matched.iter().all(|&is_match| is_match) || self.states[state].pcs.is_empty() {
+                return;
+            }
+
+            let Some((unit, len)) = decode(haystack, pos) else {
+                break;
+            };
+            pos += len;
+            state = self.next_state(program, state, unit);
+        }
+
+        for pattern_id in matches_at_end(program, &self.states[state].pcs, haystack.is_empty()) {
+            matched[pattern_id] = true;
+        }
+    }
+
+    // Returns true if the program matches starting exactly at `pos`, reading the haystack backwards from
+    // there if `backwards` is set (for the reversed programs of reverse.rs). Every unit read takes one
+    // from `budget`, None if it runs out first. Only for caches made with DfaCache::anchored.
+    pub fn is_match_at(&mut self, program: &Program, haystack: &[u8], pos: usize, backwards: bool, budget: &mut usize) -> Option<bool> {
+        self.clear_if_full();
+        // a reversed program's ^ is the original $, so it holds at the end of the haystack
+        let at_start = if backwards { pos == haystack.len() } else { pos == 0 };
+        let start_pcs = if at_start { self.start_pcs.clone() } else { self.restart_pcs.clone() };
+        let mut state = self.add_state(program, start_pcs);
+
+        let mut pos = pos;
+        loop {
+            if !self.states[state].matches.is_empty() {
+                return Some(true);
+            }
+            if self.states[state].pcs.is_empty() {
+                return Some(false);
+            }
+
+            let next = if backwards { decode_last(haystack, pos) } else { decode(haystack, pos) };
+            let Some((unit, len)) = next else {
+                break;
+            };
+            *budget = budget.checked_sub(1)?;
+            pos = if backwards { pos - len } else { pos + len };
+            state = self.next_state(program, state, unit);
+        }
+
+        Some(!matches_at_end(program, &self.states[state].pcs, haystack.is_empty()).is_empty())
+    }
+
+    fn next_state(&mut self, program: &Program, state: usize, unit: Unit) -> usize {
+        let cached = match unit {
+            Unit::Char(c) if c.is_ascii() => self.states[state].ascii_transitions[c as usize],
+            _ => self.states[state].other_transitions.get(&unit).copied().unwrap_or(UNKNOWN),
+        };
+
+        if cached != UNKNOWN { cached } else { self.compute_transition(program, state, unit) }
+    }
+
+    fn compute_transition(&mut self, program: &Program, state: usize, unit: Unit) -> usize {
+        let mut next_pcs: Vec<usize> = self.states[state]
+            .pcs
+            .iter()
+            .filter(|&&pc| matches!(&program.insts[pc], Inst::Char(node) if match_unit(node, unit)))
+            .map(|pc| pc + 1)
+            .collect();
+        next_pcs = closure(program, &next_pcs, false, false);
+        if !self.anchored {
+            next_pcs.extend_from_slice(&self.restart_pcs);
+        }
+        next_pcs.sort_unstable();
+        next_pcs.dedup();
+
+        // when the cache is full everything is dropped, including `state`, so
+        // the transition is simply not recorded this time
+        if self.states.len() >= MAX_CACHED_STATES {
+            self.clear();
+            return self.add_state(program, next_pcs);
+        }
+
+        let next = self.add_state(program, next_pcs);
+        match unit {
+            Unit::Char(c) if c.is_ascii() => self.states[state].ascii_transitions[c as usize] = next,
+            _ => {
+                self.states[state].other_transitions.insert(unit, next);
+            }
+        }
+        next
+    }
+
+    fn add_state(&mut self, program: &Program, mut pcs: Vec<usize>) -> usize {
+        pcs.sort_unstable();
+        pcs.dedup();
+
+        if let Some(&id) = self.state_ids.get(&pcs) {
+            return id;
+        }
+
+        let id = self.states.len();
+        self.states.push(State {
+            matches: match_patterns(program, &pcs),
+            pcs: pcs.clone(),
+            ascii_transitions: [UNKNOWN; 128],
+            other_transitions: HashMap::new(),
+        });
+        self.state_ids.insert(pcs, id);
+        id
+    }
+
+    fn clear_if_full(&mut self) {
+        if self.states.len() >= MAX_CACHED_STATES {
+            self.clear();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.state_ids.clear();
+    }
+}
+
+
+// All pcs reachable from `pcs` without consuming input, keeping only the ones that wait
+// for input (Char), for the end of the line (EndOfLine) or that are a Match
+fn closure(program: &Program, pcs: &[usize], at_start: bool, at_end: bool) -> Vec<usize> {
+    let mut visited = vec![false; program.insts.len()];
+    let mut stack: Vec<usize> = pcs.iter().rev().copied().collect();
+    let mut result = vec![];
+
+    while let Some(pc) = stack.pop() {
+        // pc 0 of an empty program (an empty RegexSet) leads nowhere, nothing ever matches
+        let Some(inst) = program.insts.get(pc) else {
+            continue;
+        };
+        if visited[pc] {
+            continue;
+        }
+        visited[pc] = true;
+
+        match inst {
+            Inst::Jmp(target) => stack.push(*target),
+            Inst::Split(preferred, other) => {
+                stack.push(*other);
+                stack.push(*preferred);
+            }
+            Inst::Save(_) => stack.push(pc + 1),
+            Inst::StartOfLine => {
+                if at_start {
+                    stack.push(pc + 1);
+                }
+            }
+            Inst::EndOfLine if at_end => stack.push(pc + 1),
+            Inst::EndOfLine | Inst::Char(_) | Inst::Match(_) => result.push(pc),
+            Inst::Backreference(_) => unreachable!("the lazy DFA can't match backreferences"),
+            Inst::LineStart(_) | Inst::LineEnd(_) => unreachable!("the lazy DFA can't match multi-line anchors"),
+        }
+    }
+
+    result
+}
+
+
+// At the end of the input the pending EndOfLine assertions hold, returns the patterns they lead to a Match of
+fn matches_at_end(program: &Program, pcs: &[usize], at_start: bool) -> Vec<usize> {
+    let after_end: Vec<usize> = pcs.iter().filter(|&&pc| program.insts[pc] == Inst::EndOfLine).map(|pc| pc + 1).collect();
+    match_patterns(program, &closure(program, &after_end, at_start, true))
+}
+
+
+fn match_patterns(program: &Program, pcs: &[usize]) -> Vec<usize> {
+    pcs.iter()
+        .filter_map(|&pc| match program.insts[pc] {
+            Inst::Match(pattern_id) => Some(pattern_id),
+            _ => None,
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::{compile, compile_set};
+    use crate::pattern::RegexAst;
+    use crate::pikevm;
+    use crate::regex::{max_group_id, MatchSemantics};
+    use crate::simplify::simplify;
+    use crate::utils::pattern_to_ast;
+
+    fn parse(pattern: &str) -> RegexAst {
+        simplify(pattern_to_ast(pattern).unwrap())
+    }
+
+    fn compile_pattern(pattern: &str) -> Program {
+        let ast = parse(pattern);
+        compile(&ast, max_group_id(&ast) as usize)
+    }
+
+    fn is_match(pattern: &str, haystack: &str) -> bool {
+        let program = compile_pattern(pattern);
+        DfaCache::new(&program).is_match(&program, haystack.as_bytes())
+    }
+
+    #[test]
+    fn empty_program_never_matches() {
+        let program = compile_set(&[]);
+        let mut cache = DfaCache::new(&program);
+        assert!(!cache.is_match(&program, b""));
+        assert!(!cache.is_match(&program, b"abc"));
+    }
+
+    #[test]
+    fn cache_is_flushed_mid_search() {
+        // which of the last 13 chars are a is part of the state, that's 2^13 states for the 4096 the cache keeps
+        let program = compile_pattern(&format!("(a|b)*a{}$", "(a|b)".repeat(12)));
+        let mut seed = 12345u32;
+        let mut haystack: Vec<u8> = (0..20_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                if seed >> 16 & 1 == 0 { b'a' } else { b'b' }
+            })
+            .collect();
+
+        let mut cache = DfaCache::new(&program);
+        for last in [b'a', b'b'] {
+            let len = haystack.len();
+            haystack[len - 13] = last;
+            let expected = pikevm::search(&program, &haystack, 0, false, MatchSemantics::LeftmostFirst).is_some();
+            assert_eq!(expected, last == b'a');
+            assert_eq!(cache.is_match(&program, &haystack), expected);
+            assert!(cache.states.len() <= MAX_CACHED_STATES);
+        }
+    }
+
+    #[test]
+    fn end_of_line_holds_at_the_end_of_the_input() {
+        assert!(is_match("abc$", "xxabc"));
+        assert!(!is_match("abc$", "abcx"));
+        assert!(is_match("c$|x", "abc"));
+        assert!(is_match("^$", ""));
+        assert!(!is_match("^$", "a"));
+        assert!(is_match("a(b|$)", "xa"));
+
+        // after reading abc the only thing left is the $, which matches_at_end resolves
+        let program = compile_pattern("abc$");
+        let end_of_line = program.insts.iter().position(|inst| *inst == Inst::EndOfLine).unwrap();
+        assert_eq!(closure(&program, &[end_of_line], false, false), [end_of_line]);
+        assert_eq!(matches_at_end(&program, &[end_of_line], false), [0]);
+    }
+
+    #[test]
+    fn anchored_start_stops_at_the_dead_state() {
+        let program = compile_pattern("^abc");
+        let mut cache = DfaCache::new(&program);
+        let haystack = format!("abd{}", "0123456789xyz".repeat(1000));
+        assert!(!cache.is_match(&program, haystack.as_bytes()));
+        // the start state, a, ab and the dead state after d, nothing is built for the rest
+        assert_eq!(cache.states.len(), 4);
+        assert!(cache.is_match(&program, b"abcd"));
+        assert!(!cache.is_match(&program, b"xabc"));
+    }
+
+    #[test]
+    fn which_match_marks_every_matching_pattern() {
+        let asts: Vec<RegexAst> = ["abc", "^x", r"d$", "zzz", "b+c"].iter().map(|pattern| parse(pattern)).collect();
+        let program = compile_set(&asts);
+        let mut cache = DfaCache::new(&program);
+
+        let mut matched = vec![false; asts.len()];
+        cache.which_match(&program, b"xabcd", &mut matched);
+        assert_eq!(matched, [true, true, true, false, true]);
+
+        let mut matched = vec![false; asts.len()];
+        cache.which_match(&program, b"abx zzzd!", &mut matched);
+        assert_eq!(matched, [false, false, false, true, false]);
+    }
+
+    #[test]
+    fn is_match_at_backwards() {
+        // read backwards, "cba" matches text that reads abc
+        let program = compile_pattern("cba");
+        let mut cache = DfaCache::anchored(&program);
+        let mut budget = usize::MAX;
+        assert_eq!(cache.is_match_at(&program, b"xabcx", 4, true, &mut budget), Some(true));
+        assert_eq!(cache.is_match_at(&program, b"xabcx", 5, true, &mut budget), Some(false));
+        assert_eq!(cache.is_match_at(&program, b"xabcx", 4, false, &mut budget), Some(false));
+
+        // backwards the ^ of a program only holds at the end of the haystack
+        let program = compile_pattern("^cba");
+        let mut cache = DfaCache::anchored(&program);
+        assert_eq!(cache.is_match_at(&program, b"abc", 3, true, &mut budget), Some(true));
+        assert_eq!(cache.is_match_at(&program, b"abcd", 3, true, &mut budget), Some(false));
+
+        // é is read as one char, not as its last byte
+        let program = compile_pattern("xé");
+        let mut cache = DfaCache::anchored(&program);
+        assert_eq!(cache.is_match_at(&program, "éx".as_bytes(), 3, true, &mut budget), Some(true));
+
+        // every unit read takes one from the budget
+        let mut budget = 2;
+        assert_eq!(cache.is_match_at(&program, "aéx".as_bytes(), 4, true, &mut budget), Some(true));
+        assert_eq!(budget, 0);
+        assert_eq!(cache.is_match_at(&program, "aéx".as_bytes(), 4, true, &mut budget), None);
+    }
+}
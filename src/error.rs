@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+
+/// Everything that can go wrong while compiling a pattern or searching with it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Error {
+    #[error("unclosed group: '(' at position {0} has no matching ')'")]
+    UnclosedGroup(usize),
+
+    #[error("unmatched ')' at position {0}")]
+    UnmatchedParen(usize),
+
+    #[error("unclosed character class: '[' at position {0} has no matching ']'")]
+    UnclosedClass(usize),
+
+    #[error("pattern ends with a lone '\\'")]
+    TrailingBackslash,
+
+    #[error("invalid group name at position {0}, names are letters, digits and '_' not starting with a digit")]
+    InvalidGroupName(usize),
+
+    #[error("invalid escape at position {0}, \\x has to be followed by two hex digits")]
+    InvalidHexEscape(usize),
+
+    #[error("duplicate group name '{0}'")]
+    DuplicateGroupName(String),
+
+    #[error("compiled pattern has {size} instructions, more than the limit of {limit}")]
+    SizeLimitExceeded { size: usize, limit: usize },
+
+    #[error("backtracking gave up after {0} steps, the pattern is too expensive for this input")]
+    BacktrackLimitExceeded(usize),
+
+    #[error("backtracking nested deeper than {0} levels, the input is too long for this pattern")]
+    RecursionLimitExceeded(usize),
+}
@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::pattern::{RegexAst, Repetition};
+
+
+// Builds a plain English, multi-line description of the pattern's AST.
+// The first line describes the whole pattern, followed by one line per capture group
+// and one line per back-reference saying which group it points to.
+pub fn explain_ast(pattern: &str, ast: &RegexAst) -> String {
+    let mut groups = BTreeMap::new();
+    let mut backreferences = BTreeSet::new();
+    collect_groups(ast, &mut groups, &mut backreferences);
+
+    let mut lines = vec![format!("pattern: {}", pattern), format!("  matches: {}", describe(ast))];
+
+    for (group_id, (name, node)) in &groups {
+        match name {
+            Some(name) => lines.push(format!("  group {} ({}): {}", group_id, name, describe(node))),
+            None => lines.push(format!("  group {}: {}", group_id, describe(node))),
+        }
+    }
+
+    for group_id in backreferences {
+        if groups.contains_key(&group_id) {
+            lines.push(format!("  \\{} refers to group {}", group_id, group_id));
+        } else {
+            lines.push(format!("  \\{} refers to group {}, which does not exist (never matches)", group_id, group_id));
+        }
+    }
+
+    lines.join("\n")
+}
+
+
+fn collect_groups<'a>(node: &'a RegexAst, groups: &mut BTreeMap<u32, (Option<&'a str>, &'a RegexAst)>, backreferences: &mut BTreeSet<u32>) {
+    match node {
+        RegexAst::Concat(nodes) | RegexAst::Alternate(nodes) => {
+            for node in nodes {
+                collect_groups(node, groups, backreferences);
+            }
+        }
+        RegexAst::Repeat(node, _) => collect_groups(node, groups, backreferences),
+        RegexAst::CaptureGroup(group_id, name, node) => {
+            groups.insert(*group_id, (name.as_deref(), node.as_ref()));
+            collect_groups(node, groups, backreferences);
+        }
+        RegexAst::Backreference(group_id) => {
+            backreferences.insert(*group_id);
+        }
+        _ => {}
+    }
+}
+
+
+fn describe(node: &RegexAst) -> String {
+    match node {
+        RegexAst::Concat(nodes) if nodes.is_empty() => "nothing".to_string(),
+        RegexAst::Concat(nodes) => nodes.iter().map(describe).collect::<Vec<_>>().join(", then "),
+
+        RegexAst::Alternate(nodes) => {
+            let options: Vec<String> = nodes.iter().map(|node| format!("({})", describe(node))).collect();
+            format!("either {}", options.join(" or "))
+        }
+
+        RegexAst::Repeat(inner, repetition) => match repetition {
+            Repetition::None => describe(inner),
+            Repetition::Plus => format!("one or more {}", describe_plural(inner)),
+            Repetition::Star => format!("zero or more {}", describe_plural(inner)),
+            Repetition::Optional => format!("optionally {}", describe(inner)),
+            Repetition::LazyPlus => format!("one or more {} (as few as possible)", describe_plural(inner)),
+            Repetition::LazyStar => format!("zero or more {} (as few as possible)", describe_plural(inner)),
+            Repetition::LazyOptional => format!("optionally {} (preferring to skip it)", describe(inner)),
+        },
+
+        RegexAst::CaptureGroup(group_id, _, _) => format!("group {}", group_id),
+        RegexAst::Digit => "a digit".to_string(),
+        RegexAst::Word => "a word character".to_string(),
+        RegexAst::PositiveGroup(group) => format!("one of the characters [{}]", group),
+        RegexAst::NegativeGroup(group) => format!("any character except [{}]", group),
+        RegexAst::Literal(c) => format!("literal '{}'", c),
+        RegexAst::LiteralString(text) => format!("literal '{}'", text),
+        RegexAst::Byte(byte) => format!("the raw byte 0x{:02x} (not part of valid UTF-8)", byte),
+        RegexAst::Wildcard(terminator) => format!("any character except {}", terminator_name(*terminator)),
+        RegexAst::AnyChar => "any character, newlines included".to_string(),
+        RegexAst::StartOfLine => "start of line".to_string(),
+        RegexAst::EndOfLine => "end of line".to_string(),
+        RegexAst::LineStart(_) => "start of any line".to_string(),
+        RegexAst::LineEnd(_) => "end of any line".to_string(),
+        RegexAst::Backreference(group_id) => format!("the same text group {} captured (\\{})", group_id, group_id),
+    }
+}
+
+
+// Same as describe() but reads naturally after "one or more" / "zero or more"
+fn describe_plural(node: &RegexAst) -> String {
+    match node {
+        RegexAst::Digit => "digits".to_string(),
+        RegexAst::Word => "word characters".to_string(),
+        RegexAst::PositiveGroup(group) => format!("characters from [{}]", group),
+        RegexAst::NegativeGroup(group) => format!("characters not in [{}]", group),
+        RegexAst::Literal(c) => format!("'{}' characters", c),
+        RegexAst::LiteralString(text) => format!("repetitions of '{}'", text),
+        RegexAst::Byte(byte) => format!("raw 0x{:02x} bytes", byte),
+        RegexAst::Wildcard(terminator) => format!("characters of any kind except {}", terminator_name(*terminator)),
+        RegexAst::AnyChar => "characters of any kind, newlines included".to_string(),
+        RegexAst::CaptureGroup(group_id, _, _) => format!("repetitions of group {}", group_id),
+        _ => format!("repetitions of ({})", describe(node)),
+    }
+}
+
+
+// How the line terminator . doesn't match is called in the description
+fn terminator_name(terminator: u8) -> String {
+    match terminator {
+        b'\n' => "newline".to_string(),
+        b'\0' => "NUL".to_string(),
+        byte => format!("0x{:02x}", byte),
+    }
+}
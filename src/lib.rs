@@ -0,0 +1,40 @@
+//! The regex engine behind `codecrafters-grep`, usable as a library.
+//!
+//! ```
+//! use codecrafters_grep::Regex;
+//!
+//! let re = Regex::new(r"(\d+)-(\d+)").unwrap();
+//! let caps = re.captures("call 555-1234 now").unwrap();
+//! assert_eq!(&caps[1], "555");
+//! assert_eq!(re.replace_all("1-2 and 3-4", "$2-$1"), "2-1 and 4-3");
+//! ```
+//!
+//! - [`pattern`] has the AST the parser produces
+//! - [`utils`] has the parser ([`utils::pattern_to_ast`])
+//! - [`Regex`] compiles a pattern and runs the matcher on it
+//! - [`RegexSet`] runs many patterns at once and tells which of them matched
+//! - [`memchr`] has the vectorised byte and substring search the literal scans run on
+
+mod backtrack;
+mod dfa;
+pub mod error;
+pub mod explain;
+pub mod literal;
+mod matcher;
+pub mod memchr;
+mod nfa;
+pub mod pattern;
+mod pikevm;
+pub mod regex;
+pub mod replacer;
+mod reverse;
+pub mod set;
+mod simplify;
+pub mod utils;
+mod utf8;
+
+pub use crate::error::Error;
+pub use crate::literal::RequiredLiterals;
+pub use crate::regex::{CaptureMatches, Captures, FindMatches, Match, MatchSemantics, Regex, RegexBuilder, Split, TryFindByteMatches, TryFindMatches};
+pub use crate::replacer::Replacer;
+pub use crate::set::{RegexSet, SetMatches};
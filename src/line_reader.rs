@@ -0,0 +1,209 @@
+use std::io::{self, ErrorKind, Read};
+
+use codecrafters_grep::memchr::memchr;
+
+
+// How much is read at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+
+// Where search_lines gets its lines from. Lines end at a terminator byte, '\n' unless -z makes it '\0'.
+pub trait LineSource {
+    // The next line, split like str::lines: on the terminator with a '\r' before a '\n' dropped, and
+    // no empty line after a final terminator. None at the end of the input.
+    fn next_line(&mut self) -> io::Result<Option<&[u8]>>;
+}
+
+
+// Reads the input a chunk at a time and hands it out line by line, so only the current chunk and the
+// line being read have to be in memory, not the whole file. A line that doesn't fit into what's left
+// of the buffer is moved to the front of it, the buffer only grows for lines longer than a chunk.
+pub struct LineReader<R> {
+    reader: R,
+    terminator: u8,
+    buf: Vec<u8>,
+    start: usize,       // first byte that hasn't been handed out yet
+    searched_to: usize, // no terminator between start and here
+    end: usize,         // end of what has been read into buf
+    eof: bool,
+}
+
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R, terminator: u8) -> LineReader<R> {
+        LineReader { reader, terminator, buf: vec![0; CHUNK_SIZE], start: 0, searched_to: 0, end: 0, eof: false }
+    }
+
+    fn line(&self, start: usize, end: usize) -> &[u8] {
+        trim_line(&self.buf[start..end], self.terminator)
+    }
+
+    // Reads the next chunk behind the unfinished line
+    fn fill(&mut self) -> io::Result<()> {
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.searched_to -= self.start;
+        self.start = 0;
+
+        if self.buf.len() - self.end < CHUNK_SIZE {
+            self.buf.resize(self.end + CHUNK_SIZE, 0);
+        } else if self.buf.len() > 2 * (self.end + CHUNK_SIZE) {
+            // a long line is done, give the memory back
+            self.buf.truncate(self.end + CHUNK_SIZE);
+            self.buf.shrink_to_fit();
+        }
+
+        match self.reader.read(&mut self.buf[self.end..]) {
+            Ok(0) => self.eof = true,
+            Ok(read) => self.end += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+
+impl<R: Read> LineSource for LineReader<R> {
+    fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            if let Some(offset) = memchr(self.terminator, &self.buf[self.searched_to..self.end]) {
+                let (line_start, line_end) = (self.start, self.searched_to + offset);
+                self.start = line_end + 1;
+                self.searched_to = self.start;
+                return Ok(Some(self.line(line_start, line_end)));
+            }
+            self.searched_to = self.end;
+
+            if self.eof {
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                // the last line has no terminator
+                let line_start = self.start;
+                self.start = self.end;
+                return Ok(Some(self.line(line_start, self.end)));
+            }
+
+            self.fill()?;
+        }
+    }
+}
+
+
+// Lines of input that is in memory already (like a mapped file), handed out without copying
+pub struct SliceLines<'a> {
+    rest: &'a [u8],
+    terminator: u8,
+    done: bool,
+}
+
+
+impl<'a> SliceLines<'a> {
+    pub fn new(contents: &'a [u8], terminator: u8) -> SliceLines<'a> {
+        SliceLines { rest: contents.strip_suffix(&[terminator]).unwrap_or(contents), terminator, done: contents.is_empty() }
+    }
+}
+
+
+impl LineSource for SliceLines<'_> {
+    fn next_line(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.done {
+            return Ok(None);
+        }
+        let line = match memchr(self.terminator, self.rest) {
+            Some(end) => {
+                let line = &self.rest[..end];
+                self.rest = &self.rest[end + 1..];
+                line
+            }
+            None => {
+                self.done = true;
+                self.rest
+            }
+        };
+        Ok(Some(trim_line(line, self.terminator)))
+    }
+}
+
+
+// A '\r' before a '\n' is part of the line ending, before any other terminator it's part of the line
+pub fn trim_line(line: &[u8], terminator: u8) -> &[u8] {
+    if terminator == b'\n' {
+        line.strip_suffix(b"\r").unwrap_or(line)
+    } else {
+        line
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out 1, 2 or 3 bytes per read, so lines end up split at every possible place
+    struct Trickle<'a> {
+        rest: &'a [u8],
+        reads: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            let len = (self.reads % 3 + 1).min(self.rest.len()).min(buf.len());
+            buf[..len].copy_from_slice(&self.rest[..len]);
+            self.rest = &self.rest[len..];
+            Ok(len)
+        }
+    }
+
+    fn collect(mut source: impl LineSource) -> Vec<Vec<u8>> {
+        let mut lines = vec![];
+        while let Some(line) = source.next_line().unwrap() {
+            lines.push(line.to_vec());
+        }
+        lines
+    }
+
+    // The lines from a trickling reader, checked against the ones SliceLines finds in memory
+    fn lines(input: &[u8], terminator: u8) -> Vec<Vec<u8>> {
+        let read = collect(LineReader::new(Trickle { rest: input, reads: 0 }, terminator));
+        assert_eq!(read, collect(SliceLines::new(input, terminator)));
+        read
+    }
+
+    #[test]
+    fn lines_split_across_reads() {
+        assert_eq!(lines(b"first line\nsecond\n\nlast\n", b'\n'), vec![&b"first line"[..], b"second", b"", b"last"]);
+    }
+
+    #[test]
+    fn line_straddling_the_chunk_boundary() {
+        let mut input = vec![b'a'; CHUNK_SIZE - 3];
+        input.extend_from_slice(b"\nbbbbbb\nc\n");
+        assert_eq!(lines(&input, b'\n'), vec![vec![b'a'; CHUNK_SIZE - 3], b"bbbbbb".to_vec(), b"c".to_vec()]);
+    }
+
+    #[test]
+    fn line_longer_than_a_chunk() {
+        let long = vec![b'x'; 2 * CHUNK_SIZE + 17];
+        let input = [&b"short\n"[..], &long, b"\nafter\n"].concat();
+        assert_eq!(lines(&input, b'\n'), vec![b"short".to_vec(), long, b"after".to_vec()]);
+    }
+
+    #[test]
+    fn last_line_without_terminator() {
+        assert_eq!(lines(b"one\ntwo", b'\n'), vec![&b"one"[..], b"two"]);
+        assert_eq!(lines(b"only", b'\n'), vec![&b"only"[..]]);
+        assert!(lines(b"", b'\n').is_empty());
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        assert_eq!(lines(b"one\r\ntwo\r\nthree", b'\n'), vec![&b"one"[..], b"two", b"three"]);
+        // only the '\r' right before the '\n' is part of the line ending
+        assert_eq!(lines(b"a\rb\r\r\n", b'\n'), vec![&b"a\rb\r"[..]]);
+        // with -z a '\r' is just another byte
+        assert_eq!(lines(b"one\r\0two\r\n\0", b'\0'), vec![&b"one\r"[..], b"two\r\n"]);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::pattern::{RegexAst, Repetition};
+
+
+/// Literal text that every match of a pattern has to contain, found by walking the AST.
+/// Used as a prefilter: a line that doesn't contain the literal can't match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequiredLiterals {
+    /// Every match starts with this (may be empty).
+    pub prefix: String,
+    /// Every match ends with this (may be empty).
+    pub suffix: String,
+    /// Every match contains all of these somewhere, longest first.
+    pub inner: Vec<String>,
+}
+
+
+impl RequiredLiterals {
+    /// The literal the prefilter scans for: the longest one that is known to be required.
+    pub fn best(&self) -> Option<&str> {
+        [self.prefix.as_str(), self.suffix.as_str()]
+            .into_iter()
+            .chain(self.inner.iter().map(|literal| literal.as_str()))
+            .filter(|literal| !literal.is_empty())
+            .max_by_key(|literal| literal.chars().count())
+    }
+}
+
+
+/// Finds the literals every match of `ast` must contain.
+pub fn extract_literals(ast: &RegexAst) -> RequiredLiterals {
+    let info = analyze(ast);
+
+    let mut inner = info.required;
+    inner.retain(|literal| !literal.is_empty());
+    inner.sort_by(|a, b| b.chars().count().cmp(&a.chars().count()).then_with(|| a.cmp(b)));
+    inner.dedup();
+
+    RequiredLiterals { prefix: info.prefix, suffix: info.suffix, inner }
+}
+
+
+// What is known about the text matched by one node
+struct LiteralInfo {
+    exact: Option<String>, // the node only ever matches this exact text
+    prefix: String,
+    suffix: String,
+    required: Vec<String>,
+}
+
+
+impl LiteralInfo {
+    fn exact(text: String) -> LiteralInfo {
+        LiteralInfo { prefix: text.clone(), suffix: text.clone(), required: vec![text.clone()], exact: Some(text) }
+    }
+
+    fn unknown() -> LiteralInfo {
+        LiteralInfo { exact: None, prefix: String::new(), suffix: String::new(), required: vec![] }
+    }
+}
+
+
+fn analyze(node: &RegexAst) -> LiteralInfo {
+    match node {
+        RegexAst::Literal(c) => LiteralInfo::exact(c.to_string()),
+        RegexAst::LiteralString(text) => LiteralInfo::exact(text.clone()),
+
+        // anchors don't consume anything
+        RegexAst::StartOfLine | RegexAst::EndOfLine | RegexAst::LineStart(_) | RegexAst::LineEnd(_) => LiteralInfo::exact(String::new()),
+
+        RegexAst::CaptureGroup(_, _, node) => analyze(node),
+
+        RegexAst::Concat(nodes) => analyze_concat(nodes),
+
+        RegexAst::Alternate(nodes) => {
+            let infos: Vec<LiteralInfo> = nodes.iter().map(analyze).collect();
+
+            let exact = infos.first().and_then(|first| first.exact.clone()).filter(|text| infos.iter().all(|info| info.exact.as_ref() == Some(text)));
+            if let Some(text) = exact {
+                return LiteralInfo::exact(text);
+            }
+
+            let prefix = infos.iter().map(|info| info.prefix.clone()).reduce(|a, b| common_prefix(&a, &b)).unwrap_or_default();
+            let suffix = infos.iter().map(|info| info.suffix.clone()).reduce(|a, b| common_suffix(&a, &b)).unwrap_or_default();
+            LiteralInfo { exact: None, required: vec![prefix.clone(), suffix.clone()], prefix, suffix }
+        }
+
+        // at least one repetition, so whatever the node needs is needed
+        RegexAst::Repeat(node, Repetition::Plus | Repetition::LazyPlus) => {
+            let info = analyze(node);
+            LiteralInfo { exact: None, ..info }
+        }
+        RegexAst::Repeat(node, Repetition::None) => analyze(node),
+
+        // zero repetitions are allowed, nothing is required
+        RegexAst::Repeat(_, _) => LiteralInfo::unknown(),
+
+        RegexAst::Digit
+        | RegexAst::Word
+        | RegexAst::PositiveGroup(_)
+        | RegexAst::NegativeGroup(_)
+        | RegexAst::Wildcard(_)
+        | RegexAst::AnyChar
+        | RegexAst::Byte(_)
+        | RegexAst::Backreference(_) => LiteralInfo::unknown(),
+    }
+}
+
+
+fn analyze_concat(nodes: &[RegexAst]) -> LiteralInfo {
+    let infos: Vec<LiteralInfo> = nodes.iter().map(analyze).collect();
+
+    if infos.iter().all(|info| info.exact.is_some()) {
+        return LiteralInfo::exact(infos.iter().map(|info| info.exact.clone().unwrap()).collect());
+    }
+
+    // exact parts from the start, then the prefix of the first inexact part
+    let mut prefix = String::new();
+    for info in &infos {
+        match &info.exact {
+            Some(text) => prefix.push_str(text),
+            None => {
+                prefix.push_str(&info.prefix);
+                break;
+            }
+        }
+    }
+
+    let mut suffix = String::new();
+    for info in infos.iter().rev() {
+        match &info.exact {
+            Some(text) => suffix.insert_str(0, text),
+            None => {
+                suffix.insert_str(0, &info.suffix);
+                break;
+            }
+        }
+    }
+
+    // runs of neighbouring parts are joined: the suffix of one part, the exact parts after
+    // it and the prefix of the next inexact part are all matched back to back
+    let mut required = vec![prefix.clone(), suffix.clone()];
+    let mut run = String::new();
+    for info in &infos {
+        required.extend(info.required.iter().cloned());
+        match &info.exact {
+            Some(text) => run.push_str(text),
+            None => {
+                run.push_str(&info.prefix);
+                required.push(run);
+                run = info.suffix.clone();
+            }
+        }
+    }
+    required.push(run);
+
+    LiteralInfo { exact: None, prefix, suffix, required }
+}
+
+
+fn common_prefix(a: &str, b: &str) -> String {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).map(|(x, _)| x).collect()
+}
+
+
+fn common_suffix(a: &str, b: &str) -> String {
+    let reversed: String = a.chars().rev().zip(b.chars().rev()).take_while(|(x, y)| x == y).map(|(x, _)| x).collect();
+    reversed.chars().rev().collect()
+}
+
+
+
+// The bytes every match can start with, None if there are more than 3 (too many for memchr3 to be worth
+// it) or if the pattern can match the empty string
+pub(crate) fn start_bytes(ast: &RegexAst) -> Option<Vec<u8>> {
+    let mut bytes = first_bytes(ast)?;
+    bytes.sort_unstable();
+    bytes.dedup();
+    Some(bytes).filter(|bytes| bytes.len() <= 3)
+}
+
+
+fn first_bytes(node: &RegexAst) -> Option<Vec<u8>> {
+    let first_byte = |c: char| c.to_string().as_bytes()[0];
+
+    match node {
+        RegexAst::Literal(c) => Some(vec![first_byte(*c)]),
+        RegexAst::LiteralString(text) => text.chars().next().map(|c| vec![first_byte(c)]),
+        RegexAst::PositiveGroup(group) if !group.is_empty() => Some(group.chars().map(first_byte).collect()),
+
+        // anchors don't consume anything, the first byte comes from what follows them
+        RegexAst::Concat(nodes) => nodes.iter().find(|node| !matches!(node, RegexAst::StartOfLine | RegexAst::EndOfLine | RegexAst::LineStart(_) | RegexAst::LineEnd(_))).and_then(first_bytes),
+
+        RegexAst::Alternate(nodes) => nodes.iter().map(first_bytes).try_fold(vec![], |mut all, bytes| {
+            all.extend(bytes?);
+            Some(all)
+        }),
+
+        RegexAst::CaptureGroup(_, _, node) => first_bytes(node),
+        RegexAst::Repeat(node, Repetition::None | Repetition::Plus | Repetition::LazyPlus) => first_bytes(node),
+
+        // a raw byte may sit in the middle of a valid char, where no match can start
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simplify::simplify;
+    use crate::utils::pattern_to_ast;
+
+    fn parse(pattern: &str) -> RegexAst {
+        simplify(pattern_to_ast(pattern).unwrap())
+    }
+
+    fn literals(pattern: &str) -> RequiredLiterals {
+        extract_literals(&parse(pattern))
+    }
+
+    fn required(prefix: &str, suffix: &str, inner: &[&str]) -> RequiredLiterals {
+        RequiredLiterals { prefix: prefix.to_string(), suffix: suffix.to_string(), inner: inner.iter().map(|literal| literal.to_string()).collect() }
+    }
+
+    #[test]
+    fn alternations() {
+        assert_eq!(literals("foobar|foobaz"), required("fooba", "", &["fooba"]));
+        assert_eq!(literals("abc|xbc"), required("", "bc", &["bc"]));
+        assert_eq!(literals("(foo|bar)baz"), required("", "baz", &["baz"]));
+        // nothing in common, no single literal is required
+        assert_eq!(literals("hello|world"), RequiredLiterals::default());
+        assert_eq!(literals("hello|world").best(), None);
+    }
+
+    #[test]
+    fn optional_and_star_prefixes() {
+        // a match doesn't have to start with what may be repeated zero times
+        assert_eq!(literals("a?bcd"), required("", "bcd", &["bcd"]));
+        assert_eq!(literals("(?:abc)?def"), required("", "def", &["def"]));
+        assert_eq!(literals("x*hello"), required("", "hello", &["hello"]));
+        assert_eq!(literals("(ab)*cd"), required("", "cd", &["cd"]));
+        assert_eq!(literals("(?:ab|cd)*"), RequiredLiterals::default());
+        // + has to match at least once
+        assert_eq!(literals("a+bc").prefix, "a");
+    }
+
+    #[test]
+    fn group_as_the_whole_pattern() {
+        assert_eq!(literals("(hello)"), required("hello", "hello", &["hello"]));
+        assert_eq!(literals("(hello world)").best(), Some("hello world"));
+        assert_eq!(literals("(abc|abd)"), required("ab", "", &["ab"]));
+    }
+
+    #[test]
+    fn start_bytes_of_literals_and_alternations() {
+        assert_eq!(start_bytes(&parse("abc")), Some(vec![b'a']));
+        assert_eq!(start_bytes(&parse("^abc")), Some(vec![b'a']));
+        assert_eq!(start_bytes(&parse("hello|world")), Some(vec![b'h', b'w']));
+        assert_eq!(start_bytes(&parse("(foo|bar)baz")), Some(vec![b'b', b'f']));
+        assert_eq!(start_bytes(&parse("[xy]abc")), Some(vec![b'x', b'y']));
+        assert_eq!(start_bytes(&parse("éa")), Some(vec!["é".as_bytes()[0]]));
+        // more than 3 aren't worth scanning for
+        assert_eq!(start_bytes(&parse("ab|cd|ef|gh")), None);
+        assert_eq!(start_bytes(&parse("[abcd]x")), None);
+    }
+
+    #[test]
+    fn wildcards_and_classes_disable_start_bytes() {
+        // the literal is still required, but a match can start with any byte
+        assert_eq!(literals(".abc").suffix, "abc");
+        assert_eq!(start_bytes(&parse(".abc")), None);
+        assert_eq!(start_bytes(&parse(r"\dfoo")), None);
+        assert_eq!(start_bytes(&parse(r"\wfoo")), None);
+        assert_eq!(start_bytes(&parse("[^x]foo")), None);
+        // and so does anything that can match the empty string
+        assert_eq!(start_bytes(&parse("a?bcd")), None);
+        assert_eq!(start_bytes(&parse("x*hello")), None);
+    }
+}
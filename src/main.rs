@@ -1,338 +1,347 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
 use std::env;
-use std::fs;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::process;
-use std::vec;
 
-use crate::pattern::RegexAst;
-use crate::pattern::Repetition;
-use crate::utils::match_pattern_with_char;
-use crate::utils::pattern_to_ast;
+use codecrafters_grep::explain::explain_ast;
+use codecrafters_grep::memchr::memchr;
+use codecrafters_grep::utils::{escape, pattern_to_ast};
+use codecrafters_grep::{MatchSemantics, Regex, RegexBuilder, RegexSet};
+
+use crate::line_reader::{trim_line, LineReader, LineSource, SliceLines};
+#[cfg(unix)]
+use crate::mmap::Mmap;
+use crate::options::{parse_args, print_usage, Options};
+mod line_reader;
+#[cfg(unix)]
+mod mmap;
 mod old_main;
-mod pattern;
-mod utils;
-
-
-// Returns all possible end positions after matching this node starting from input_ind
-fn solve(input_chars: &[char], node: &RegexAst, input_ind: usize, captures: &mut HashMap<u32, String>) -> Vec<usize> {
-    match node {
-        // Single character matchers
-        RegexAst::Digit
-        | RegexAst::Word
-        | RegexAst::PositiveGroup(_)
-        | RegexAst::NegativeGroup(_)
-        | RegexAst::Literal(_)
-        | RegexAst::Wildcard => {
-            if input_ind < input_chars.len() && match_pattern_with_char(node, input_chars[input_ind])
-            {
-                vec![input_ind + 1]
-            } else {
-                vec![]
+mod options;
+
+
+// Stands in for the filename of stdin unless --label gives another name
+const STDIN_LABEL: &str = "(standard input)";
+
+
+// What the search turned up, decides the exit code
+#[derive(Default)]
+struct Outcome {
+    found_match: bool,
+    had_error: bool, // some line couldn't be searched
+}
+
+
+fn search_directory_recursive(dir_path: &str, set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    if let Ok(entries) = fs::read_dir(dir_path) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let path_str = path.to_string_lossy();
+            
+            if path.is_file() {
+                search_in_file(&path_str, set, options, outcome);
+            } else if path.is_dir() {
+                search_directory_recursive(&path_str, set, options, outcome);
             }
         }
+    }
+}
 
-        RegexAst::StartOfLine => {
-            if input_ind == 0 {
-                vec![0] // Matches at start, consumes no characters
-            } else {
-                vec![]
-            }
+
+fn search_in_file(filename: &str, set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    match File::open(filename) {
+        Ok(file) => {
+            search_file(Some(filename), file, set, options, outcome);
+        }
+        Err(_) => {
+            // Skip files that can't be read (e.g., permission issues)
+            // In real grep, this might print an error, but we'll silently skip
         }
+    }
+}
 
-        RegexAst::EndOfLine => {
-            if input_ind == input_chars.len() {
-                vec![input_ind] // Matches at end, consumes no characters
+// Big files are searched in place through a memory map, everything else is read in chunks
+fn search_file(filename: Option<&str>, file: File, set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    #[cfg(unix)]
+    if !options.no_mmap {
+        if let Some(map) = Mmap::map_if_large(&file) {
+            if options.multiline {
+                search_buffer(filename, &map, set, options, outcome);
             } else {
-                vec![]
+                search_lines(filename, SliceLines::new(&map, terminator(options)), set, options, outcome);
             }
+            return;
         }
+    }
+    search_input(filename, file, set, options, outcome);
+}
 
 
-        RegexAst::Backreference(group_id) => {
-            let captured_group_text = captures.get(group_id);
+fn search_input(filename: Option<&str>, mut input: impl Read, set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    if !options.multiline {
+        search_lines(filename, LineReader::new(input, terminator(options)), set, options, outcome);
+        return;
+    }
 
-            if let Some(captured_text) = captured_group_text {
-                let captured_group_text: Vec<char> = captured_text.chars().collect();
+    // a match may span any number of lines, so all of the input has to be there
+    let mut contents = vec![];
+    match input.read_to_end(&mut contents) {
+        Ok(_) => search_buffer(filename, &contents, set, options, outcome),
+        Err(err) => {
+            eprintln!("Error reading {}: {}", filename.unwrap_or("input"), err);
+            outcome.had_error = true;
+        }
+    }
+}
 
-                let remaining_input_len = input_chars.len() - input_ind;
-                if remaining_input_len < captured_group_text.len() {
-                    return vec![];
-                }
 
-                // now we can do the comparsion ;) 
-                if input_chars[input_ind..input_ind + captured_group_text.len()] == captured_group_text[..] {
-                    return vec![input_ind + captured_group_text.len()];
+// -U: the patterns run over all of the contents at once. Every line a match touches is printed once,
+// the first line of each match with its line number.
+fn search_buffer(filename: Option<&str>, contents: &[u8], set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    let prefix = filename.map(|filename| format!("{}:", filename)).unwrap_or_default();
+    let terminator = terminator(options);
+    let mut stdout = io::stdout().lock();
+
+    // the matches of every pattern, in the order they start in
+    let mut matches = vec![];
+    for (index, regex) in set.regexes().iter().enumerate() {
+        for range in regex.try_find_iter_bytes(contents) {
+            match range {
+                Ok(range) => matches.push((range, index)),
+                Err(err) => {
+                    eprintln!("{}{}", prefix, err);
+                    outcome.had_error = true;
+                    break;
                 }
             }
-
-            vec![]
         }
-
-
-        RegexAst::CaptureGroup(group_id, ast) => {
-            let end_positions = solve(input_chars, ast, input_ind, captures);
-            
-            if !end_positions.is_empty() {
-                // Only capture if there was at least one match
-                let last_end = *end_positions.iter().max().unwrap();
-                let matched_text: String = input_chars[input_ind..last_end].iter().collect();
-                captures.insert(*group_id, matched_text);
+    }
+    matches.sort_by_key(|(range, index)| (range.start, *index));
+    outcome.found_match |= !matches.is_empty();
+
+    let mut line_start = 0; // of the line the last match started in
+    let mut line_number = 1;
+    let mut printed_to = 0; // everything before this has been printed
+    for (range, index) in matches {
+        let prefix = format!("{}{}", prefix, pattern_tag(options, &[index]));
+        if options.only_matching {
+            if !range.is_empty() {
+                print_line(&mut stdout, &prefix, &contents[range], terminator);
             }
-            
-            end_positions
+            continue;
         }
 
-
-        RegexAst::Alternate(regex_asts) => {
-            let mut all_results = HashSet::new();
-            
-            for option in regex_asts {
-                // Save current capture state for backtracking
-                let saved_captures = captures.clone();
-                
-                let results = solve(input_chars, option, input_ind, captures);
-                
-                if results.is_empty() {
-                    // This alternative failed, restore captures
-                    *captures = saved_captures;
-                } else {
-                    // This alternative succeeded, keep the captures and add results
-                    for end_pos in results {
-                        all_results.insert(end_pos);
-                    }
-                    // For now, we'll take the first successful alternative
-                    // In a more sophisticated implementation, we'd try all alternatives
-                    break;
-                }
-            }
-            
-            all_results.into_iter().collect()
+        while let Some(offset) = memchr(terminator, &contents[line_start..range.start]) {
+            line_start += offset + 1;
+            line_number += 1;
+        }
+        // an empty match after the final terminator isn't on any line
+        if line_start == contents.len() {
+            continue;
         }
 
-        RegexAst::Concat(regex_asts) => {
-            let mut current_positions = vec![input_ind];
+        // through the end of the line the last byte of the match is on
+        let last = if range.is_empty() { range.start } else { range.end - 1 };
+        let block_end = memchr(terminator, &contents[last..]).map_or(contents.len(), |offset| last + offset);
+        if block_end < printed_to {
+            continue;
+        }
 
-            for ast in regex_asts {
-                let mut next_positions = HashSet::new();
-                for &pos in &current_positions {
-                    for end_pos in solve(input_chars, ast, pos, captures) {
-                        next_positions.insert(end_pos);
-                    }
-                }
-                current_positions = next_positions.into_iter().collect();
-                if current_positions.is_empty() {
-                    break; // Early termination if no matches possible
-                }
+        let block_start = line_start.max(printed_to);
+        for (ind, line) in contents[block_start..block_end].split(|&byte| byte == terminator).enumerate() {
+            let line = trim_line(line, terminator);
+            if ind == 0 && block_start == line_start {
+                print_line(&mut stdout, &format!("{}{}:", prefix, line_number), line, terminator);
+            } else {
+                print_line(&mut stdout, &prefix, line, terminator);
             }
-            current_positions
         }
+        printed_to = block_end + 1;
+    }
+}
 
 
-
-        RegexAst::Repeat(regex_ast, repetition) => {
-            match repetition {
-                Repetition::None => solve(input_chars, regex_ast, input_ind, captures),
-
-                Repetition::Optional => {
-                    let mut results = HashSet::new();
-                    results.insert(input_ind); // Zero matches
-
-                    for end_pos in solve(input_chars, regex_ast, input_ind, captures) {
-                        results.insert(end_pos); // One match
+// Prints the matching lines of the input, prefixed with the filename if there is one. The input
+// doesn't have to be valid UTF-8, lines are printed with their original bytes.
+fn search_lines(filename: Option<&str>, mut lines: impl LineSource, set: &RegexSet, options: &Options, outcome: &mut Outcome) {
+    let prefix = filename.map(|filename| format!("{}:", filename)).unwrap_or_default();
+    let terminator = terminator(options);
+    let mut stdout = io::stdout().lock();
+
+    for line_index in 0.. {
+        let line = match lines.next_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                // whatever was read before still counts, the rest of the input is lost
+                eprintln!("{}line {}: {}", prefix, line_index + 1, err);
+                outcome.had_error = true;
+                break;
+            }
+        };
+
+        let result = set.try_matches_bytes(line).and_then(|matches| {
+            if options.only_matching {
+                // -o prints every non-empty match on its own line, in the order they start in
+                let mut ranges = vec![];
+                for index in matches.iter() {
+                    for range in set.regexes()[index].try_find_iter_bytes(line) {
+                        ranges.push((range?, index));
                     }
-                    results.into_iter().collect()
                 }
-
-                Repetition::Star => {
-                    let mut results = HashSet::new();
-                    let mut current_positions = vec![input_ind];
-                    results.insert(input_ind); // Zero matches
-
-                    loop {
-                        let mut next_positions = HashSet::new();
-                        let mut found_new = false;
-
-                        for &pos in &current_positions {
-                            for end_pos in solve(input_chars, regex_ast, pos, captures) {
-                                if !results.contains(&end_pos) {
-                                    found_new = true;
-                                    next_positions.insert(end_pos);
-                                    results.insert(end_pos);
-                                }
-                            }
-                        }
-
-                        if !found_new {
-                            break;
-                        }
-                        current_positions = next_positions.into_iter().collect();
+                ranges.sort_by_key(|(range, index)| (range.start, *index));
+                for (range, index) in ranges {
+                    if !range.is_empty() {
+                        print_line(&mut stdout, &format!("{}{}", prefix, pattern_tag(options, &[index])), &line[range], terminator);
                     }
-                    results.into_iter().collect()
-                }
-
-                Repetition::Plus => {
-                    let mut results = HashSet::new();
-                    let mut current_positions: Vec<usize> =
-                        solve(input_chars, regex_ast, input_ind, captures);
-
-                    // Add results from first mandatory match
-                    for &pos in &current_positions {
-                        results.insert(pos);
-                    }
-
-                    // Add results from additional matches (like Star)
-                    loop {
-                        let mut next_positions = HashSet::new();
-                        let mut found_new = false;
-
-                        for &pos in &current_positions {
-                            for end_pos in solve(input_chars, regex_ast, pos, captures) {
-                                if !results.contains(&end_pos) {
-                                    found_new = true;
-                                    next_positions.insert(end_pos);
-                                    results.insert(end_pos);
-                                }
-                            }
-                        }
-
-                        if !found_new {
-                            break;
-                        }
-                        current_positions = next_positions.into_iter().collect();
-                    }
-                    results.into_iter().collect()
                 }
+            } else if matches.matched_any() {
+                let indices: Vec<usize> = matches.iter().collect();
+                print_line(&mut stdout, &format!("{}{}", prefix, pattern_tag(options, &indices)), line, terminator);
+            }
+            Ok(matches.matched_any())
+        });
+
+        match result {
+            Ok(is_match) => outcome.found_match |= is_match,
+            Err(err) => {
+                // the pattern is too expensive for this line, skip it and go on with the rest
+                eprintln!("{}line {}: {}", prefix, line_index + 1, err);
+                outcome.had_error = true;
             }
         }
     }
 }
 
 
-
-fn match_pattern(input_line: &str, pattern: &str) -> bool {
-    let ast = pattern_to_ast(pattern);
-    let input_chars: Vec<char> = input_line.trim_end().chars().collect();
-
-    eprintln!("{:?}", ast);
-    
-    // Try matching from every starting position
-    for start_pos in 0..=input_chars.len() {
-        let mut captures = HashMap::new();
-        let end_positions = solve(&input_chars, &ast, start_pos, &mut captures);
-       
-        if !end_positions.is_empty() {
-            return true;
-        }
+// With --which-pattern: "0,2:" for a line that patterns 0 and 2 matched
+fn pattern_tag(options: &Options, indices: &[usize]) -> String {
+    if !options.which_pattern {
+        return String::new();
     }
-    false
+    let indices: Vec<String> = indices.iter().map(|index| index.to_string()).collect();
+    format!("{}:", indices.join(","))
 }
 
 
+// What ends a line, in the input and in the output
+fn terminator(options: &Options) -> u8 {
+    if options.null_data { b'\0' } else { b'\n' }
+}
 
 
-fn search_directory_recursive(dir_path: &str, pattern: &str, found_match: &mut bool) {
-    if let Ok(entries) = fs::read_dir(dir_path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                let path_str = path.to_string_lossy();
-                
-                if path.is_file() {
-                    search_in_file(&path_str, pattern, found_match);
-                } else if path.is_dir() {
-                    search_directory_recursive(&path_str, pattern, found_match);
-                }
-            }
-        }
+fn print_line(out: &mut impl Write, prefix: &str, text: &[u8], terminator: u8) {
+    let written = out.write_all(prefix.as_bytes()).and_then(|_| out.write_all(text)).and_then(|_| out.write_all(&[terminator]));
+    // nowhere left to write to (e.g. the other end of a pipe is gone), like grep just stop
+    if written.is_err() {
+        process::exit(2);
     }
 }
 
 
-fn search_in_file(filename: &str, pattern: &str, found_match: &mut bool) {
-    match fs::read_to_string(filename) {
-        Ok(file_contents) => {
-            // Process each line in the file
-            for line in file_contents.lines() {
-                if match_pattern(line, pattern) {
-                    println!("{}:{}", filename, line);
-                    *found_match = true;
-                }
-            }
-        }
-        Err(_) => {
-            // Skip files that can't be read (e.g., binary files, permission issues)
-            // In real grep, this might print an error, but we'll silently skip
+fn compile_or_exit(pattern: &str, builder: &RegexBuilder) -> Regex {
+    match builder.build() {
+        Ok(regex) => regex,
+        Err(err) => {
+            eprintln!("Invalid pattern {}: {}", pattern, err);
+            process::exit(2);
         }
     }
 }
 
-// Usage: echo <input_text> | your_program.sh -E <pattern>
-// Or: your_program.sh -E <pattern> <filename1> [filename2] [...]
+// What the matcher will run on, and which literal (if any) lets it skip lines early
+fn print_debug_pattern(regex: &Regex) {
+    let literals = regex.required_literals();
+    eprintln!("ast: {:?}", regex.ast());
+    eprintln!("prefix literal: {:?}", literals.prefix);
+    eprintln!("suffix literal: {:?}", literals.suffix);
+    eprintln!("required literals: {:?}", literals.inner);
+    match literals.best() {
+        Some(literal) => eprintln!("prefilter: lines without {:?} are skipped", literal),
+        None => eprintln!("prefilter: none"),
+    }
+}
+
+// Usage: cat <input_file> | your_program.sh -E <pattern>
+// Or: your_program.sh [-r] [-o] [-F] [-U] [-z] [--posix] [--debug-pattern] [--which-pattern] [--no-mmap] [--label NAME] [--backtrack-limit N] [--regex-size-limit N] -E <pattern> [-e <pattern>...] <filename_or_directory1> [...]
+// Or: your_program.sh --explain <pattern>
 fn main() {
     eprintln!("Logs from your program will appear here!");
 
     let args: Vec<String> = env::args().collect();
-    
-    if args.len() < 3 {
-        println!("Usage: {} [-r] -E <pattern> [filename_or_directory...]", args[0]);
-        process::exit(1);
-    }
-    
-    let mut recursive = false;
-    let mut arg_index = 1;
-    
-    // Check for -r flag
-    if args[arg_index] == "-r" {
-        recursive = true;
-        arg_index += 1;
+
+    // --explain only describes the pattern, no searching
+    if args.len() == 3 && args[1] == "--explain" {
+        // the pattern as written, not the simplified tree the regex runs on
+        let ast = match pattern_to_ast(&args[2]) {
+            Ok(ast) => ast,
+            Err(err) => {
+                eprintln!("Invalid pattern {}: {}", args[2], err);
+                process::exit(2);
+            }
+        };
+        println!("{}", explain_ast(&args[2], &ast));
+        process::exit(0);
     }
     
-    if arg_index >= args.len() || args[arg_index] != "-E" {
-        println!("Expected '-E' flag");
+    if args.len() < 3 {
+        print_usage(&args[0]);
         process::exit(1);
     }
-    
-    arg_index += 1; // Move past -E
-    
-    if arg_index >= args.len() {
-        println!("Expected pattern after -E");
-        process::exit(1);
+
+    let options = parse_args(&args);
+    let recursive = options.recursive;
+
+    let semantics = if options.posix { MatchSemantics::LeftmostLongest } else { MatchSemantics::LeftmostFirst };
+    // every pattern gets the same settings, the set then matches them all in one pass
+    let regexes = options.patterns.iter().map(|pattern| {
+        // -F: the pattern is searched for as it is, which the regex turns into a plain substring search
+        let mut builder = if options.fixed_strings { RegexBuilder::new(&escape(pattern)) } else { RegexBuilder::new(pattern) };
+        builder.match_semantics(semantics);
+        // -U: the whole input is one haystack, ^ and $ still mean the start and end of a line
+        builder.multi_line(options.multiline);
+        // -z: records end with '\0', . matches '\n' like any other char but never crosses into the next record
+        builder.line_terminator(terminator(&options));
+        if let Some(limit) = options.backtrack_limit {
+            builder.backtrack_limit(limit);
+        }
+        if let Some(limit) = options.size_limit {
+            builder.size_limit(limit);
+        }
+        compile_or_exit(pattern, &builder)
+    }).collect();
+    let set = RegexSet::from_regexes(regexes);
+
+    if options.debug_pattern {
+        for regex in set.regexes() {
+            print_debug_pattern(regex);
+        }
     }
     
-    let pattern = &args[arg_index];
-    arg_index += 1;
-    
-    let mut found_match = false;
+    let mut outcome = Outcome::default();
 
-    if arg_index < args.len() {
+    if !options.paths.is_empty() {
         // File/directory mode
-        let paths = &args[arg_index..];
+        let paths = &options.paths;
+        let multiple_targets = paths.len() > 1 || recursive;
         
         for path_str in paths {
             let path = Path::new(path_str);
             
-            if recursive && path.is_dir() {
+            if path_str == "-" {
+                // like in grep "-" is stdin
+                let label = options.label.as_deref().unwrap_or(STDIN_LABEL);
+                let filename = if multiple_targets { Some(label) } else { None };
+                search_input(filename, io::stdin().lock(), &set, &options, &mut outcome);
+            } else if recursive && path.is_dir() {
                 // Recursive directory search
-                search_directory_recursive(path_str, pattern, &mut found_match);
+                search_directory_recursive(path_str, &set, &options, &mut outcome);
             } else if path.is_file() {
                 // File search
-                let multiple_targets = paths.len() > 1 || recursive;
-                
-                match fs::read_to_string(path_str) {
-                    Ok(file_contents) => {
-                        for line in file_contents.lines() {
-                            if match_pattern(line, pattern) {
-                                if multiple_targets {
-                                    println!("{}:{}", path_str, line);
-                                } else {
-                                    println!("{}", line);
-                                }
-                                found_match = true;
-                            }
-                        }
+                match File::open(path_str) {
+                    Ok(file) => {
+                        let filename = if multiple_targets { Some(path_str.as_str()) } else { None };
+                        search_file(filename, file, &set, &options, &mut outcome);
                     }
                     Err(err) => {
                         eprintln!("Error reading file {}: {}", path_str, err);
@@ -348,17 +357,15 @@ fn main() {
             }
         }
     } else {
-        // Stdin mode: read from standard input
-        let mut input_line = String::new();
-        io::stdin().read_line(&mut input_line).unwrap();
-        
-        if match_pattern(&input_line, pattern) {
-            found_match = true;
-        }
+        // Stdin mode: all of standard input, line by line like a file
+        search_input(None, io::stdin().lock(), &set, &options, &mut outcome);
     }
 
-    if found_match {
+    // like grep: 0 if something matched, 2 if a line couldn't be searched, 1 otherwise
+    if outcome.found_match {
         process::exit(0);
+    } else if outcome.had_error {
+        process::exit(2);
     } else {
         process::exit(1);
     }
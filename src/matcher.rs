@@ -0,0 +1,414 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::vec;
+
+use crate::error::Error;
+use crate::pattern::RegexAst;
+use crate::pattern::Repetition;
+use crate::utf8::decode;
+use crate::utils::{is_line_end, is_line_start, match_unit};
+
+
+// Maps a group id to the (start, end) byte offsets it last matched on one match path
+pub type CaptureMap = BTreeMap<u32, (usize, usize)>;
+
+// One way of matching a node: where it ends and what the groups captured on the way
+pub type MatchPath = (usize, CaptureMap);
+
+
+// How much work one search may do before it gives up. Every call to solve and solve_star is
+// a step (as is every instruction the bounded backtracker runs), and since they recurse on the
+// Rust stack their nesting depth is limited too.
+pub struct Budget {
+    steps: usize,
+    max_steps: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+
+impl Budget {
+    pub fn new(max_steps: usize, max_depth: usize) -> Budget {
+        Budget { steps: 0, max_steps, depth: 0, max_depth }
+    }
+
+    // One unit of work that doesn't nest
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(Error::BacktrackLimitExceeded(self.max_steps));
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.step()?;
+        if self.depth >= self.max_depth {
+            return Err(Error::RecursionLimitExceeded(self.max_depth));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+
+// Returns all possible ways to match this node starting from input_ind given the captures
+// made so far on this path, ordered from most to least preferred (greedy repeats prefer
+// longer, lazy ones shorter). Every path carries its own captures so a backreference
+// always sees what its own path captured. Fails once the budget is used up.
+//
+// Repeats whose body can match nothing follow the NFA engines (and Perl and RE2): on "c" (?:c*?)*
+// prefers the empty match. Only when such repeats are nested in each other the NFA engines can still
+// pick a different match, there it depends on which thread reaches an instruction first.
+pub fn solve(haystack: &[u8], node: &RegexAst, input_ind: usize, captures: &CaptureMap, budget: &mut Budget) -> Result<Vec<MatchPath>, Error> {
+    budget.enter()?;
+    let result = solve_node(haystack, node, input_ind, captures, budget);
+    budget.leave();
+    result
+}
+
+
+fn solve_node(haystack: &[u8], node: &RegexAst, input_ind: usize, captures: &CaptureMap, budget: &mut Budget) -> Result<Vec<MatchPath>, Error> {
+    let paths = match node {
+        // Single character matchers
+        RegexAst::Digit
+        | RegexAst::Word
+        | RegexAst::PositiveGroup(_)
+        | RegexAst::NegativeGroup(_)
+        | RegexAst::Literal(_)
+        | RegexAst::Byte(_)
+        | RegexAst::Wildcard(_)
+        | RegexAst::AnyChar => match decode(haystack, input_ind) {
+            Some((unit, len)) if match_unit(node, unit) => vec![(input_ind + len, captures.clone())],
+            _ => vec![],
+        },
+
+        RegexAst::LiteralString(text) => {
+            if haystack[input_ind..].starts_with(text.as_bytes()) {
+                vec![(input_ind + text.len(), captures.clone())]
+            } else {
+                vec![]
+            }
+        }
+
+        RegexAst::StartOfLine => {
+            if input_ind == 0 {
+                vec![(0, captures.clone())] // Matches at start, consumes no characters
+            } else {
+                vec![]
+            }
+        }
+
+        RegexAst::EndOfLine => {
+            if input_ind == haystack.len() {
+                vec![(input_ind, captures.clone())] // Matches at end, consumes no characters
+            } else {
+                vec![]
+            }
+        }
+
+        RegexAst::LineStart(terminator) => {
+            if is_line_start(haystack, input_ind, *terminator) {
+                vec![(input_ind, captures.clone())]
+            } else {
+                vec![]
+            }
+        }
+
+        RegexAst::LineEnd(terminator) => {
+            if is_line_end(haystack, input_ind, *terminator) {
+                vec![(input_ind, captures.clone())]
+            } else {
+                vec![]
+            }
+        }
+
+
+        RegexAst::Backreference(group_id) => {
+            if let Some(&(group_start, group_end)) = captures.get(group_id) {
+                let group_len = group_end - group_start;
+
+                let remaining_input_len = haystack.len() - input_ind;
+                if remaining_input_len < group_len {
+                    return Ok(vec![]);
+                }
+
+                // now we can do the comparsion ;)
+                if haystack[input_ind..input_ind + group_len] == haystack[group_start..group_end] {
+                    return Ok(vec![(input_ind + group_len, captures.clone())]);
+                }
+            }
+
+            vec![]
+        }
+
+
+        RegexAst::CaptureGroup(group_id, _, ast) => {
+            // each path records where the group ended on that path
+            solve(haystack, ast, input_ind, captures, budget)?
+                .into_iter()
+                .map(|(end_pos, mut path_captures)| {
+                    path_captures.insert(*group_id, (input_ind, end_pos));
+                    (end_pos, path_captures)
+                })
+                .collect()
+        }
+
+
+        RegexAst::Alternate(regex_asts) => {
+            let mut all_results = vec![];
+
+            // Every branch is explored so later nodes can backtrack into the other ones,
+            // paths of earlier branches come first since they are preferred (leftmost-first)
+            for option in regex_asts {
+                // every branch starts from the same captures, nothing to restore on failure
+                extend_unique(&mut all_results, solve(haystack, option, input_ind, captures, budget)?);
+            }
+
+            all_results
+        }
+
+        RegexAst::Concat(regex_asts) => {
+            let mut current_paths = vec![(input_ind, captures.clone())];
+
+            for ast in regex_asts {
+                let mut next_paths = vec![];
+                for (pos, path_captures) in &current_paths {
+                    extend_unique(&mut next_paths, solve(haystack, ast, *pos, path_captures, budget)?);
+                }
+                current_paths = next_paths;
+                if current_paths.is_empty() {
+                    break; // Early termination if no matches possible
+                }
+            }
+            current_paths
+        }
+
+
+
+        RegexAst::Repeat(regex_ast, repetition) => {
+            match repetition {
+                Repetition::None => solve(haystack, regex_ast, input_ind, captures, budget)?,
+
+                Repetition::Optional | Repetition::LazyOptional => {
+                    let once = solve(haystack, regex_ast, input_ind, captures, budget)?;
+                    let zero = (input_ind, captures.clone());
+
+                    let mut results = vec![];
+                    if repetition.is_lazy() {
+                        results.push(zero); // Zero matches first
+                        extend_unique(&mut results, once);
+                    } else {
+                        extend_unique(&mut results, once);
+                        extend_unique(&mut results, [zero]);
+                    }
+                    results
+                }
+
+                Repetition::Star | Repetition::LazyStar | Repetition::Plus | Repetition::LazyPlus => {
+                    // Works like the NFA's node+, and (?:node+)? for *: only the first repetition may match
+                    // nothing and then it's the last one (with its captures). Any later one would start
+                    // where the one before ended, solve_star drops those.
+                    let lazy = repetition.is_lazy();
+                    let zero = matches!(repetition, Repetition::Star | Repetition::LazyStar).then(|| (input_ind, captures.clone()));
+
+                    let mut results = vec![];
+                    if lazy {
+                        extend_unique(&mut results, zero.clone());
+                    }
+                    let mut memo = HashMap::new();
+                    for (end_pos, path_captures) in solve(haystack, regex_ast, input_ind, captures, budget)? {
+                        if end_pos == input_ind {
+                            extend_unique(&mut results, [(end_pos, path_captures)]);
+                        } else {
+                            extend_unique(&mut results, solve_star(haystack, regex_ast, end_pos, lazy, &path_captures, &mut memo, budget)?);
+                        }
+                    }
+                    if !lazy {
+                        extend_unique(&mut results, zero);
+                    }
+                    results
+                }
+            }
+        }
+    };
+    Ok(paths)
+}
+
+
+// Paths of `node` repeated zero or more times starting from input_ind, most preferred first.
+// Greedy tries one more repetition before stopping there, lazy stops before trying one more.
+// memo caches the result per (position, captures) since different paths reach the same state.
+fn solve_star(
+    haystack: &[u8],
+    node: &RegexAst,
+    input_ind: usize,
+    lazy: bool,
+    captures: &CaptureMap,
+    memo: &mut HashMap<MatchPath, Vec<MatchPath>>,
+    budget: &mut Budget,
+) -> Result<Vec<MatchPath>, Error> {
+    let state = (input_ind, captures.clone());
+    if let Some(results) = memo.get(&state) {
+        return Ok(results.clone());
+    }
+    budget.enter()?;
+
+    let mut results = vec![];
+    if lazy {
+        results.push(state.clone());
+    }
+
+    for (end_pos, path_captures) in solve(haystack, node, input_ind, captures, budget)? {
+        // an empty repetition can't lead anywhere new
+        if end_pos != input_ind {
+            extend_unique(&mut results, solve_star(haystack, node, end_pos, lazy, &path_captures, memo, budget)?);
+        }
+    }
+
+    if !lazy {
+        extend_unique(&mut results, [state.clone()]);
+    }
+
+    memo.insert(state, results.clone());
+    budget.leave();
+    Ok(results)
+}
+
+
+// Appends the paths that aren't in results yet, keeping the order of preference
+fn extend_unique(results: &mut Vec<MatchPath>, paths: impl IntoIterator<Item = MatchPath>) {
+    for path in paths {
+        if !results.contains(&path) {
+            results.push(path);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrack;
+    use crate::nfa::compile;
+    use crate::regex::max_group_id;
+    use crate::simplify::simplify;
+    use crate::utils::pattern_to_ast;
+
+    // Span of the whole match and of every group, None for groups that didn't participate
+    type Spans = Vec<Option<(usize, usize)>>;
+
+    fn parse(pattern: &str) -> RegexAst {
+        simplify(pattern_to_ast(pattern).unwrap())
+    }
+
+    fn budget() -> Budget {
+        Budget::new(1_000_000, 1_000)
+    }
+
+    // The leftmost-first match found by the bounded backtracker over the NFA
+    fn backtrack_spans(pattern: &str, haystack: &str) -> Option<Spans> {
+        let ast = parse(pattern);
+        let program = compile(&ast, max_group_id(&ast) as usize);
+        let slots = backtrack::search(&program, haystack.as_bytes(), 0, false, &mut budget()).unwrap()?;
+        Some(slots.chunks(2).map(|slot| Some((slot[0]?, slot[1]?))).collect())
+    }
+
+    // The match solve() finds at the leftmost start, picking the first path (leftmost-first)
+    // or the first of the longest paths (leftmost-longest)
+    fn solve_spans(pattern: &str, haystack: &str, longest: bool) -> Option<Spans> {
+        let ast = parse(pattern);
+        let group_count = max_group_id(&ast);
+        let haystack = haystack.as_bytes();
+        (0..=haystack.len()).find_map(|start| {
+            let paths = solve(haystack, &ast, start, &CaptureMap::new(), &mut budget()).unwrap();
+            let max_end = paths.iter().map(|(end, _)| *end).max()?;
+            let (end, captures) = paths.into_iter().find(|(end, _)| !longest || *end == max_end).unwrap();
+
+            let mut spans = vec![Some((start, end))];
+            spans.extend((1..=group_count).map(|group_id| captures.get(&group_id).copied()));
+            Some(spans)
+        })
+    }
+
+    // Every matcher that runs backreferences has to agree on the answer
+    fn assert_spans(pattern: &str, haystack: &str, expected: Option<Spans>) {
+        assert_eq!(backtrack_spans(pattern, haystack), expected, "backtrack {} on {:?}", pattern, haystack);
+        assert_eq!(solve_spans(pattern, haystack, false), expected, "solve {} on {:?}", pattern, haystack);
+        assert_eq!(solve_spans(pattern, haystack, true), expected, "solve longest {} on {:?}", pattern, haystack);
+    }
+
+    #[test]
+    fn backreference_to_a_later_alternative() {
+        assert_spans(r"(a|ab)(c|bcd)\2", "abcdbcd", Some(vec![Some((0, 7)), Some((0, 1)), Some((1, 4))]));
+        // a fails with both c and bcd, only ab followed by c and another c matches
+        assert_spans(r"(a|ab)(c|bcd)\2", "abcc", Some(vec![Some((0, 4)), Some((0, 2)), Some((2, 3))]));
+        assert_spans(r"(a|ab)(c|bcd)\2", "abcbcd", None);
+    }
+
+    #[test]
+    fn backreference_to_a_repeat_of_several_lengths() {
+        assert_spans(r"(\w+) \1", "hello hello", Some(vec![Some((0, 11)), Some((0, 5))]));
+        assert_spans(r"(\w+) \1", "abc bc", Some(vec![Some((1, 6)), Some((1, 3))]));
+        assert_spans(r"(\w+) \1", "aa a", Some(vec![Some((1, 4)), Some((1, 2))]));
+        assert_spans(r"(\w+) \1", "ab cd", None);
+    }
+
+    #[test]
+    fn anchored_backreference() {
+        assert_spans(r"^(\w+) \1$", "abc bc", None);
+        assert_spans(r"^(\w+) \1$", "abc abc", Some(vec![Some((0, 7)), Some((0, 3))]));
+    }
+
+    #[test]
+    fn backreference_after_wildcard_repeat() {
+        assert_spans(r"(.*)-\1", "ab-ab", Some(vec![Some((0, 5)), Some((0, 2))]));
+        assert_spans(r"(.*)-\1", "xab-ab", Some(vec![Some((1, 6)), Some((1, 3))]));
+        // .* has to give back all of -b to match an empty group
+        assert_spans(r"(.*)-\1", "a-b-b", Some(vec![Some((1, 2)), Some((1, 1))]));
+    }
+
+    #[test]
+    fn backreference_to_group_that_did_not_participate() {
+        assert_spans(r"(a)?b\1", "b", None);
+        assert_spans(r"(a)?b\1", "aba", Some(vec![Some((0, 3)), Some((0, 1))]));
+        assert_spans(r"(?:(a)|b)\1", "b", None);
+        assert_spans(r"(?:(a)|b)\1", "baa", Some(vec![Some((1, 3)), Some((1, 2))]));
+    }
+
+    // Leftmost-first only, the longest match of these is a different one
+    fn assert_first_spans(pattern: &str, haystack: &str, expected: Option<Spans>) {
+        assert_eq!(backtrack_spans(pattern, haystack), expected, "backtrack {} on {:?}", pattern, haystack);
+        assert_eq!(solve_spans(pattern, haystack, false), expected, "solve {} on {:?}", pattern, haystack);
+    }
+
+    #[test]
+    fn only_the_first_repetition_may_match_nothing() {
+        assert_first_spans("(?:c*?)*", "c", Some(vec![Some((0, 0))]));
+        assert_first_spans("(?:((?:a)*?))*", "", Some(vec![Some((0, 0)), Some((0, 0))]));
+        assert_first_spans("(a*)*", "b", Some(vec![Some((0, 0)), Some((0, 0))]));
+        assert_first_spans("(|a)+", "a", Some(vec![Some((0, 0)), Some((0, 0))]));
+        // an empty repetition after one that matched something is dropped
+        assert_first_spans("(a*)*", "aa", Some(vec![Some((0, 2)), Some((0, 2))]));
+        assert_first_spans("(a|)*", "aa", Some(vec![Some((0, 2)), Some((1, 2))]));
+        assert_first_spans("(a?)*b", "aab", Some(vec![Some((0, 3)), Some((1, 2))]));
+    }
+
+    #[test]
+    fn alternation_backtracks_into_later_branches() {
+        assert_eq!(solve_spans("(a|ab)c", "abc", false), Some(vec![Some((0, 3)), Some((0, 2))]));
+        assert_eq!(solve_spans("(a|ab)c", "abc", true), Some(vec![Some((0, 3)), Some((0, 2))]));
+    }
+
+    #[test]
+    fn alternation_prefers_earlier_branches() {
+        assert_eq!(solve_spans("a|ab", "ab", false), Some(vec![Some((0, 1))]));
+        assert_eq!(solve_spans("a|ab", "ab", true), Some(vec![Some((0, 2))]));
+        assert_eq!(solve_spans("(foo|foobar)baz", "foobarbaz", false), Some(vec![Some((0, 9)), Some((0, 6))]));
+        assert_eq!(solve_spans("(foo|foobar)(bar)?", "foobar", false), Some(vec![Some((0, 6)), Some((0, 3)), Some((3, 6))]));
+    }
+}
@@ -0,0 +1,361 @@
+/// Index of the first occurrence of `needle` in `haystack`.
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    find_any([needle; 3], haystack)
+}
+
+
+/// Index of the first byte in `haystack` that is either of the needles.
+pub fn memchr2(needle1: u8, needle2: u8, haystack: &[u8]) -> Option<usize> {
+    find_any([needle1, needle2, needle2], haystack)
+}
+
+
+/// Index of the first byte in `haystack` that is any of the three needles.
+pub fn memchr3(needle1: u8, needle2: u8, needle3: u8, haystack: &[u8]) -> Option<usize> {
+    find_any([needle1, needle2, needle3], haystack)
+}
+
+
+/// Index of the first occurrence of `needle` in `haystack`, 0 for an empty needle.
+pub fn memmem(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    match needle {
+        [] => Some(0),
+        [byte] => memchr(*byte, haystack),
+        _ if needle.len() > haystack.len() => None,
+        _ => find_substring(haystack, needle),
+    }
+}
+
+
+// On x86_64 32 bytes are compared at a time with AVX2 if the CPU has it, 16 with SSE2 otherwise.
+// Other targets get a plain loop.
+#[cfg(target_arch = "x86_64")]
+fn find_any(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        unsafe { x86::find_any_avx2(needles, haystack) }
+    } else {
+        // SAFETY: every x86_64 CPU supports SSE2
+        unsafe { x86::find_any_sse2(needles, haystack) }
+    }
+}
+
+
+#[cfg(not(target_arch = "x86_64"))]
+fn find_any(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+    find_any_scalar(needles, haystack)
+}
+
+
+// The needle is at least 2 bytes and not longer than the haystack
+#[cfg(target_arch = "x86_64")]
+fn find_substring(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        unsafe { x86::find_substring_avx2(haystack, needle) }
+    } else {
+        // SAFETY: every x86_64 CPU supports SSE2
+        unsafe { x86::find_substring_sse2(haystack, needle) }
+    }
+}
+
+
+#[cfg(not(target_arch = "x86_64"))]
+fn find_substring(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    find_substring_scalar(haystack, needle)
+}
+
+
+// Also what the vectorised versions fall back to for the last few bytes
+fn find_any_scalar(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|byte| needles.contains(byte))
+}
+
+
+fn find_substring_scalar(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let Some((&first, rest)) = needle.split_first() else {
+        return Some(0);
+    };
+
+    // jump between occurrences of the first byte, only compare the rest there
+    let mut pos = 0;
+    while pos + needle.len() <= haystack.len() {
+        pos += find_any_scalar([first; 3], &haystack[pos..=haystack.len() - needle.len()])?;
+        if haystack[pos + 1..].starts_with(rest) {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+    None
+}
+
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{find_any_scalar, find_substring_scalar};
+
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_any_avx2(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+        let needle1 = _mm256_set1_epi8(needles[0] as i8);
+        let needle2 = _mm256_set1_epi8(needles[1] as i8);
+        let needle3 = _mm256_set1_epi8(needles[2] as i8);
+
+        let mut pos = 0;
+        while pos + 32 <= haystack.len() {
+            let chunk = _mm256_loadu_si256(haystack.as_ptr().add(pos) as *const __m256i);
+            let found = _mm256_or_si256(
+                _mm256_or_si256(_mm256_cmpeq_epi8(chunk, needle1), _mm256_cmpeq_epi8(chunk, needle2)),
+                _mm256_cmpeq_epi8(chunk, needle3),
+            );
+            // one bit per byte, the lowest set bit is the first match
+            let mask = _mm256_movemask_epi8(found) as u32;
+            if mask != 0 {
+                return Some(pos + mask.trailing_zeros() as usize);
+            }
+            pos += 32;
+        }
+
+        find_any_sse2(needles, &haystack[pos..]).map(|offset| pos + offset)
+    }
+
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_any_sse2(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+        let needle1 = _mm_set1_epi8(needles[0] as i8);
+        let needle2 = _mm_set1_epi8(needles[1] as i8);
+        let needle3 = _mm_set1_epi8(needles[2] as i8);
+
+        let mut pos = 0;
+        while pos + 16 <= haystack.len() {
+            let chunk = _mm_loadu_si128(haystack.as_ptr().add(pos) as *const __m128i);
+            let found = _mm_or_si128(_mm_or_si128(_mm_cmpeq_epi8(chunk, needle1), _mm_cmpeq_epi8(chunk, needle2)), _mm_cmpeq_epi8(chunk, needle3));
+            let mask = _mm_movemask_epi8(found) as u32;
+            if mask != 0 {
+                return Some(pos + mask.trailing_zeros() as usize);
+            }
+            pos += 16;
+        }
+
+        find_any_scalar(needles, &haystack[pos..]).map(|offset| pos + offset)
+    }
+
+
+    // Compares the first and the last byte of the needle at 32 starting positions at once, only the
+    // positions where both fit get compared in full. Needs a needle of at least 2 bytes.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_substring_avx2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        let last_offset = needle.len() - 1;
+        let first = _mm256_set1_epi8(needle[0] as i8);
+        let last = _mm256_set1_epi8(needle[last_offset] as i8);
+
+        let mut pos = 0;
+        while pos + last_offset + 32 <= haystack.len() {
+            let starts = _mm256_loadu_si256(haystack.as_ptr().add(pos) as *const __m256i);
+            let ends = _mm256_loadu_si256(haystack.as_ptr().add(pos + last_offset) as *const __m256i);
+            let candidates = _mm256_and_si256(_mm256_cmpeq_epi8(starts, first), _mm256_cmpeq_epi8(ends, last));
+
+            let mut mask = _mm256_movemask_epi8(candidates) as u32;
+            while mask != 0 {
+                let candidate = pos + mask.trailing_zeros() as usize;
+                if haystack[candidate + 1..candidate + last_offset] == needle[1..last_offset] {
+                    return Some(candidate);
+                }
+                mask &= mask - 1;
+            }
+            pos += 32;
+        }
+
+        find_substring_sse2(&haystack[pos..], needle).map(|offset| pos + offset)
+    }
+
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_substring_sse2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        let last_offset = needle.len() - 1;
+        let first = _mm_set1_epi8(needle[0] as i8);
+        let last = _mm_set1_epi8(needle[last_offset] as i8);
+
+        let mut pos = 0;
+        while pos + last_offset + 16 <= haystack.len() {
+            let starts = _mm_loadu_si128(haystack.as_ptr().add(pos) as *const __m128i);
+            let ends = _mm_loadu_si128(haystack.as_ptr().add(pos + last_offset) as *const __m128i);
+            let candidates = _mm_and_si128(_mm_cmpeq_epi8(starts, first), _mm_cmpeq_epi8(ends, last));
+
+            let mut mask = _mm_movemask_epi8(candidates) as u32;
+            while mask != 0 {
+                let candidate = pos + mask.trailing_zeros() as usize;
+                if haystack[candidate + 1..candidate + last_offset] == needle[1..last_offset] {
+                    return Some(candidate);
+                }
+                mask &= mask - 1;
+            }
+            pos += 16;
+        }
+
+        find_substring_scalar(&haystack[pos..], needle).map(|offset| pos + offset)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type FindAny = fn([u8; 3], &[u8]) -> Option<usize>;
+    type FindSubstring = fn(&[u8], &[u8]) -> Option<usize>;
+
+    // Around the 16 and 32 byte chunks and the hand-off to the shorter versions
+    const LENGTHS: [usize; 15] = [0, 1, 2, 15, 16, 17, 31, 32, 33, 47, 48, 49, 63, 64, 65];
+
+    fn naive_find_any(needles: [u8; 3], haystack: &[u8]) -> Option<usize> {
+        haystack.iter().position(|byte| needles.contains(byte))
+    }
+
+    fn naive_find_substring(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        (0..=haystack.len().checked_sub(needle.len())?).find(|&pos| haystack[pos..].starts_with(needle))
+    }
+
+    // Deterministic bytes out of a small alphabet, so partial matches of needles are common
+    fn haystack(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                b"abcd"[(state % 4) as usize]
+            })
+            .collect()
+    }
+
+    // Every find_any implementation, the scalar one and whatever the CPU can run directly
+    fn find_any_impls() -> Vec<(&'static str, FindAny)> {
+        #[allow(unused_mut)]
+        let mut impls: Vec<(&'static str, FindAny)> = vec![("dispatch", find_any), ("scalar", find_any_scalar)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            // SAFETY: every x86_64 CPU supports SSE2
+            impls.push(("sse2", |needles, haystack| unsafe { x86::find_any_sse2(needles, haystack) }));
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU supports AVX2
+                impls.push(("avx2", |needles, haystack| unsafe { x86::find_any_avx2(needles, haystack) }));
+            }
+        }
+        impls
+    }
+
+    fn find_substring_impls() -> Vec<(&'static str, FindSubstring)> {
+        #[allow(unused_mut)]
+        let mut impls: Vec<(&'static str, FindSubstring)> = vec![("dispatch", memmem), ("scalar", find_substring_scalar)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            // SAFETY: every x86_64 CPU supports SSE2
+            impls.push(("sse2", |haystack, needle| unsafe { x86::find_substring_sse2(haystack, needle) }));
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU supports AVX2
+                impls.push(("avx2", |haystack, needle| unsafe { x86::find_substring_avx2(haystack, needle) }));
+            }
+        }
+        impls
+    }
+
+    #[test]
+    fn find_any_matches_naive_search() {
+        for len in LENGTHS {
+            for seed in 0..8 {
+                let mut haystack = haystack(len, seed);
+                for needles in [*b"aaa", *b"abb", *b"cdx", *b"xyz"] {
+                    for (name, find) in find_any_impls() {
+                        assert_eq!(find(needles, &haystack), naive_find_any(needles, &haystack), "{} len {} seed {}", name, len, seed);
+                    }
+                }
+
+                // only one needle, at every position up to the very end
+                haystack.fill(b'a');
+                for pos in 0..len {
+                    haystack[pos] = b'z';
+                    for (name, find) in find_any_impls() {
+                        assert_eq!(find([b'z'; 3], &haystack), Some(pos), "{} len {} pos {}", name, len, pos);
+                        assert_eq!(find([b'y', b'x', b'z'], &haystack), Some(pos), "{} len {} pos {}", name, len, pos);
+                    }
+                    haystack[pos] = b'a';
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn public_functions_match_naive_search() {
+        for len in LENGTHS {
+            let haystack = haystack(len, len as u64);
+            assert_eq!(memchr(b'c', &haystack), naive_find_any([b'c'; 3], &haystack));
+            assert_eq!(memchr2(b'x', b'd', &haystack), naive_find_any([b'x', b'd', b'd'], &haystack));
+            assert_eq!(memchr3(b'x', b'y', b'b', &haystack), naive_find_any([b'x', b'y', b'b'], &haystack));
+            assert_eq!(memmem(&haystack, b""), Some(0));
+            assert_eq!(memmem(&haystack, b"c"), naive_find_any([b'c'; 3], &haystack));
+        }
+    }
+
+    #[test]
+    fn find_substring_matches_naive_search() {
+        for len in LENGTHS {
+            for seed in 0..8 {
+                let haystack = haystack(len, seed);
+
+                let mut needles: Vec<Vec<u8>> = vec![b"ab".to_vec(), b"dd".to_vec(), b"abc".to_vec(), b"cadb".to_vec(), b"xy".to_vec()];
+                // the tail of the haystack, up to the whole of it
+                for needle_len in [2, 3, 16, 17, 32, 33] {
+                    if needle_len <= len {
+                        needles.push(haystack[len - needle_len..].to_vec());
+                    }
+                }
+                if len >= 2 {
+                    needles.push(haystack.clone());
+                    // as long as the haystack but different in the last byte
+                    let mut almost = haystack.clone();
+                    almost[len - 1] = b'x';
+                    needles.push(almost);
+                }
+
+                for needle in &needles {
+                    for (name, find) in find_substring_impls() {
+                        if needle.len() > haystack.len() && name != "dispatch" {
+                            continue; // the vectorised versions need the needle to fit
+                        }
+                        assert_eq!(
+                            find(&haystack, needle),
+                            naive_find_substring(&haystack, needle),
+                            "{} len {} seed {} needle {:?}",
+                            name,
+                            len,
+                            seed,
+                            needle
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn two_byte_needle_at_the_very_end() {
+        for len in LENGTHS.into_iter().filter(|&len| len >= 2) {
+            let mut haystack = vec![b'a'; len];
+            haystack[len - 2] = b'x';
+            haystack[len - 1] = b'y';
+            for (name, find) in find_substring_impls() {
+                assert_eq!(find(&haystack, b"xy"), Some(len - 2), "{} len {}", name, len);
+                assert_eq!(find(&haystack, b"yx"), None, "{} len {}", name, len);
+            }
+        }
+    }
+
+    #[test]
+    fn needle_longer_than_haystack() {
+        assert_eq!(memmem(b"abc", b"abcd"), None);
+        assert_eq!(memmem(b"", b"ab"), None);
+    }
+}
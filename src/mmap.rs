@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+
+// Smaller files are cheaper to read than to map
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+
+// A whole file mapped read-only into memory, unmapped again when dropped.
+//
+// The file must not be truncated while it's mapped, reading the pages that are gone kills the process
+// with SIGBUS. grep shares that risk with every other tool that maps its input.
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+
+impl Mmap {
+    // Maps the file if it's a regular file that is big enough to be worth it
+    pub fn map_if_large(file: &File) -> Option<Mmap> {
+        let metadata = file.metadata().ok()?;
+        if !metadata.is_file() || metadata.len() < MMAP_THRESHOLD {
+            return None;
+        }
+        Mmap::map(file, usize::try_from(metadata.len()).ok()?).ok()
+    }
+
+    fn map(file: &File, len: usize) -> io::Result<Mmap> {
+        // SAFETY: a fresh read-only private mapping of an open file, nothing else refers to that memory
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // only a hint that the file is read front to back, failing doesn't matter
+        // SAFETY: the range is the mapping that was just made
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Mmap { ptr, len })
+    }
+}
+
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is len readable bytes and lives as long as self
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly what map() mapped, no slices into it outlive self
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
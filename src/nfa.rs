@@ -0,0 +1,226 @@
+use crate::pattern::{RegexAst, Repetition};
+
+
+// One instruction of a Thompson NFA program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Char(RegexAst),      // consume one char matching this single-char node (Literal, Digit, Word, ...)
+    Split(usize, usize), // try both, the first one is preferred
+    Jmp(usize),
+    Save(usize),         // record the current position in a capture slot
+    StartOfLine,
+    EndOfLine,
+    LineStart(u8),       // multi-line ^ and $ with their line terminator, see RegexAst::LineStart
+    LineEnd(u8),
+    Backreference(u32),  // only the backtracking matchers can run these
+    Match(usize),        // the pattern that matched, always 0 outside of a RegexSet
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub insts: Vec<Inst>,
+    pub slot_count: usize, // two slots (start, end) per group, group 0 is the whole match
+    pub has_backreferences: bool,
+    pub has_line_anchors: bool, // LineStart or LineEnd, which the lazy DFA can't run
+    pub reaches_backreference: Vec<bool>, // per pc, true if a Backreference can be run from there
+}
+
+
+// Compiles the AST into a program that records the whole match in slots 0 and 1
+// and group N in slots 2N and 2N + 1, then ends in Match
+pub fn compile(ast: &RegexAst, group_count: usize) -> Program {
+    let mut compiler = Compiler { insts: vec![], has_backreferences: false, has_line_anchors: false };
+
+    compiler.insts.push(Inst::Save(0));
+    compiler.compile_node(ast);
+    compiler.insts.push(Inst::Save(1));
+    compiler.insts.push(Inst::Match(0));
+
+    Program {
+        reaches_backreference: reaches_backreference(&compiler.insts),
+        insts: compiler.insts,
+        slot_count: (group_count + 1) * 2,
+        has_backreferences: compiler.has_backreferences,
+        has_line_anchors: compiler.has_line_anchors,
+    }
+}
+
+
+// Compiles several patterns into one program that runs all of them at once, pattern i ends in
+// Match(i). There are no Save instructions, the program only says which patterns match.
+pub fn compile_set(asts: &[RegexAst]) -> Program {
+    let mut compiler = Compiler { insts: vec![], has_backreferences: false, has_line_anchors: false };
+
+    // Split(pattern 0, next split) ... like an alternation, but every pattern has its own Match
+    for (pattern_id, ast) in asts.iter().enumerate() {
+        if pattern_id + 1 < asts.len() {
+            let split = compiler.push(Inst::Split(0, 0));
+            compiler.compile_node(ast);
+            compiler.push(Inst::Match(pattern_id));
+            compiler.insts[split] = Inst::Split(split + 1, compiler.insts.len());
+        } else {
+            compiler.compile_node(ast);
+            compiler.push(Inst::Match(pattern_id));
+        }
+    }
+
+    Program {
+        reaches_backreference: reaches_backreference(&compiler.insts),
+        insts: compiler.insts,
+        slot_count: 2,
+        has_backreferences: compiler.has_backreferences,
+        has_line_anchors: compiler.has_line_anchors,
+    }
+}
+
+
+// Marks every pc that has a path to a Backreference. Whether a match can be found from the other
+// pcs doesn't depend on what the groups captured, which is what the backtracker's memo relies on.
+fn reaches_backreference(insts: &[Inst]) -> Vec<bool> {
+    let mut reaches = insts.iter().map(|inst| matches!(inst, Inst::Backreference(_))).collect::<Vec<_>>();
+
+    // propagate backwards along the edges until nothing changes, jumps can go both ways
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pc in 0..insts.len() {
+            if reaches[pc] {
+                continue;
+            }
+            let next_reaches = match &insts[pc] {
+                Inst::Split(preferred, other) => reaches[*preferred] || reaches[*other],
+                Inst::Jmp(target) => reaches[*target],
+                Inst::Match(_) => false,
+                _ => reaches[pc + 1],
+            };
+            if next_reaches {
+                reaches[pc] = true;
+                changed = true;
+            }
+        }
+    }
+
+    reaches
+}
+
+
+struct Compiler {
+    insts: Vec<Inst>,
+    has_backreferences: bool,
+    has_line_anchors: bool,
+}
+
+
+impl Compiler {
+    fn compile_node(&mut self, node: &RegexAst) {
+        match node {
+            RegexAst::Concat(nodes) => {
+                for node in nodes {
+                    self.compile_node(node);
+                }
+            }
+
+            RegexAst::Alternate(nodes) => {
+                // Split(branch 1, next split) ... every branch jumps to the end when done
+                let mut jumps_to_end = vec![];
+                for (ind, node) in nodes.iter().enumerate() {
+                    if ind + 1 < nodes.len() {
+                        let split = self.push(Inst::Split(0, 0));
+                        self.compile_node(node);
+                        jumps_to_end.push(self.push(Inst::Jmp(0)));
+                        self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                    } else {
+                        self.compile_node(node);
+                    }
+                }
+                let end = self.insts.len();
+                for jump in jumps_to_end {
+                    self.insts[jump] = Inst::Jmp(end);
+                }
+            }
+
+            RegexAst::Repeat(node, repetition) => self.compile_repeat(node, *repetition),
+
+            RegexAst::CaptureGroup(group_id, _, node) => {
+                self.push(Inst::Save(*group_id as usize * 2));
+                self.compile_node(node);
+                self.push(Inst::Save(*group_id as usize * 2 + 1));
+            }
+
+            RegexAst::LiteralString(text) => {
+                for c in text.chars() {
+                    self.push(Inst::Char(RegexAst::Literal(c)));
+                }
+            }
+
+            RegexAst::StartOfLine => {
+                self.push(Inst::StartOfLine);
+            }
+
+            RegexAst::EndOfLine => {
+                self.push(Inst::EndOfLine);
+            }
+
+            RegexAst::LineStart(terminator) => {
+                self.has_line_anchors = true;
+                self.push(Inst::LineStart(*terminator));
+            }
+
+            RegexAst::LineEnd(terminator) => {
+                self.has_line_anchors = true;
+                self.push(Inst::LineEnd(*terminator));
+            }
+
+            RegexAst::Backreference(group_id) => {
+                self.has_backreferences = true;
+                self.push(Inst::Backreference(*group_id));
+            }
+
+            single_char => {
+                self.push(Inst::Char(single_char.clone()));
+            }
+        }
+    }
+
+    fn compile_repeat(&mut self, node: &RegexAst, repetition: Repetition) {
+        // Split(a, b) prefers a, so a lazy repeat just swaps the two targets
+        let split = |lazy: bool, body: usize, exit: usize| if lazy { Inst::Split(exit, body) } else { Inst::Split(body, exit) };
+
+        match repetition {
+            Repetition::None => self.compile_node(node),
+
+            Repetition::Optional | Repetition::LazyOptional => {
+                // L1: split L2, L3   L2: node   L3:
+                let start = self.push(Inst::Split(0, 0));
+                self.compile_node(node);
+                self.insts[start] = split(repetition.is_lazy(), start + 1, self.insts.len());
+            }
+
+            Repetition::Star | Repetition::LazyStar => {
+                // (?:node+)?   L1: split L2, L3   L2: node; split L2, L3   L3:
+                // Unlike a jmp back to L1, an iteration that matched nothing can still leave through
+                // its own split, so like in Perl and RE2 (?:c*?)* prefers the empty match on "c" and
+                // the groups of (a*)* are set on "b".
+                let start = self.push(Inst::Split(0, 0));
+                self.compile_node(node);
+                let exit = self.insts.len() + 1;
+                self.push(split(repetition.is_lazy(), start + 1, exit));
+                self.insts[start] = split(repetition.is_lazy(), start + 1, exit);
+            }
+
+            Repetition::Plus | Repetition::LazyPlus => {
+                // L1: node   split L1, L2   L2:
+                let start = self.insts.len();
+                self.compile_node(node);
+                let exit = self.insts.len() + 1;
+                self.push(split(repetition.is_lazy(), start, exit));
+            }
+        }
+    }
+
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+}
@@ -0,0 +1,120 @@
+use std::process;
+
+
+// Everything the command line asked for
+pub struct Options {
+    pub patterns: Vec<String>, // -E/-e, given more than once every line is searched for all of them
+    pub paths: Vec<String>,
+    pub recursive: bool,     // -r
+    pub only_matching: bool, // -o, print only the matched parts of a line
+    pub fixed_strings: bool, // -F, the patterns are plain text
+    pub multiline: bool,     // -U, search the whole input at once so a match can span lines
+    pub null_data: bool,     // -z, lines end with '\0' instead of '\n', in the input and the output
+    pub posix: bool,         // --posix, leftmost-longest instead of leftmost-first matches
+    pub debug_pattern: bool, // --debug-pattern, print the AST and the prefilter literals to stderr
+    pub which_pattern: bool, // --which-pattern, tag every hit with the indices of the patterns that matched
+    pub label: Option<String>, // --label NAME, what stdin is called when filenames are printed
+    pub no_mmap: bool,       // --no-mmap, read big files in chunks too instead of mapping them into memory
+    pub backtrack_limit: Option<usize>, // --backtrack-limit N, steps a backtracking search may take per line
+    pub size_limit: Option<usize>,      // --regex-size-limit N, instructions the pattern may compile to
+}
+
+
+pub fn print_usage(program: &str) {
+    println!("Usage: {} [-r] [-o] [-F] [-U] [-z] [--posix] [--debug-pattern] [--which-pattern] [--no-mmap] [--label NAME] [--backtrack-limit N] [--regex-size-limit N] -E <pattern> [-e <pattern>...] [filename_or_directory...]", program);
+    println!("       {} --explain <pattern>", program);
+}
+
+
+// Flags can come in any order before the files, -E and -e are always followed by a pattern
+pub fn parse_args(args: &[String]) -> Options {
+    let mut patterns = vec![];
+    let mut recursive = false;
+    let mut only_matching = false;
+    let mut fixed_strings = false;
+    let mut multiline = false;
+    let mut null_data = false;
+    let mut posix = false;
+    let mut debug_pattern = false;
+    let mut which_pattern = false;
+    let mut label = None;
+    let mut no_mmap = false;
+    let mut backtrack_limit = None;
+    let mut size_limit = None;
+
+    let mut arg_index = 1;
+    while arg_index < args.len() {
+        match args[arg_index].as_str() {
+            "-r" => recursive = true,
+            "-o" | "--only-matching" => only_matching = true,
+            "-F" | "--fixed-strings" => fixed_strings = true,
+            "-U" | "--multiline" => multiline = true,
+            "-z" | "--null-data" => null_data = true,
+            "--posix" => posix = true,
+            "--debug-pattern" => debug_pattern = true,
+            "--which-pattern" => which_pattern = true,
+            "--no-mmap" => no_mmap = true,
+            "--label" => {
+                arg_index += 1;
+                if arg_index >= args.len() {
+                    println!("Expected a name after --label");
+                    process::exit(1);
+                }
+                label = Some(args[arg_index].clone());
+            }
+            "--backtrack-limit" => {
+                arg_index += 1;
+                backtrack_limit = Some(parse_number(args, arg_index));
+            }
+            "--regex-size-limit" => {
+                arg_index += 1;
+                size_limit = Some(parse_number(args, arg_index));
+            }
+            "-E" | "-e" => {
+                arg_index += 1;
+                if arg_index >= args.len() {
+                    println!("Expected pattern after {}", args[arg_index - 1]);
+                    process::exit(1);
+                }
+                patterns.push(args[arg_index].clone());
+            }
+            // first non-flag argument, everything from here on is a file
+            _ => break,
+        }
+        arg_index += 1;
+    }
+
+    if patterns.is_empty() {
+        println!("Expected '-E' or '-e' flag");
+        process::exit(1);
+    }
+
+    Options {
+        patterns,
+        paths: args[arg_index..].to_vec(),
+        recursive,
+        only_matching,
+        fixed_strings,
+        multiline,
+        null_data,
+        posix,
+        debug_pattern,
+        which_pattern,
+        label,
+        no_mmap,
+        backtrack_limit,
+        size_limit,
+    }
+}
+
+
+// The value of a numeric flag, args[arg_index - 1] is the flag itself
+fn parse_number(args: &[String], arg_index: usize) -> usize {
+    match args.get(arg_index).map(|arg| arg.parse()) {
+        Some(Ok(number)) => number,
+        _ => {
+            println!("Expected a number after {}", args[arg_index - 1]);
+            process::exit(1);
+        }
+    }
+}
@@ -5,6 +5,17 @@ pub enum Repetition {
     Plus,     // +
     Star,     // *
     Optional, // ?
+    LazyPlus,     // +?
+    LazyStar,     // *?
+    LazyOptional, // ??
+}
+
+
+impl Repetition {
+    // Lazy repetitions prefer matching as few times as possible
+    pub fn is_lazy(self) -> bool {
+        matches!(self, Repetition::LazyPlus | Repetition::LazyStar | Repetition::LazyOptional)
+    }
 }
 
 
@@ -12,23 +23,28 @@ pub enum Repetition {
 pub enum RegexAst {
     Concat(Vec<RegexAst>),             // sequence of nodes
     Alternate(Vec<RegexAst>),          // alternation a|b|c
-    Repeat(Box<RegexAst>, Repetition), // repetition (*, +, ?)
-    CaptureGroup(u32, Box<RegexAst>),  // (cat) the u32 represents the id of the group
+    Repeat(Box<RegexAst>, Repetition), // repetition (*, +, ? and their lazy *?, +?, ??)
+    CaptureGroup(u32, Option<String>, Box<RegexAst>), // (cat) or (?P<name>cat), the u32 represents the id of the group
     Digit,                             // \d
     Word,                              // \w
     PositiveGroup(String),             // [abc]
     NegativeGroup(String),             // [^abc]
     Literal(char),                     // 'a'
-    Wildcard,                          // .
+    Byte(u8),                          // \xNN with NN >= 0x80, a raw byte that isn't valid UTF-8
+    LiteralString(String),             // 'abc', only produced by simplify()
+    Wildcard(u8),                      // ., anything but the line terminator ('\n' unless changed)
+    AnyChar,                           // . after (?s), '\n' included
     StartOfLine,                       // ^
     EndOfLine,                         // $
-    Backreference(u32),                     // e.g: \1
+    LineStart(u8),                     // ^ in multi-line mode, also right after the line terminator
+    LineEnd(u8),                       // $ in multi-line mode, also right before the line terminator
+    Backreference(u32),                // e.g: \1
 }
 
 /*
     regex = alternate
     alternate = concat ( | concat ) *
     concat = repeat +
-    repeat = atom ('*' | '+')?
-    atom = literal | group | class | anchor
+    repeat = atom (('*' | '+' | '?') '?'?)?
+    atom = literal | group | named group (?P<name>...) | non-capturing group (?:...) | class | anchor
 */
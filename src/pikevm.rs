@@ -0,0 +1,241 @@
+use std::mem;
+
+use crate::nfa::{Inst, Program};
+use crate::regex::MatchSemantics;
+use crate::utf8::decode;
+use crate::utils::{is_line_end, is_line_start, match_unit};
+
+
+// Capture slots of one thread, see nfa::compile for the layout
+pub type Slots = Vec<Option<usize>>;
+
+
+struct Thread {
+    pc: usize,
+    slots: Slots,
+}
+
+
+// The threads alive at one input position in priority order, at most one per pc
+struct ThreadList {
+    threads: Vec<Thread>,
+    visited: Vec<bool>, // pcs already added at this position, including the epsilon ones
+}
+
+
+impl ThreadList {
+    fn new(program_len: usize) -> ThreadList {
+        ThreadList { threads: vec![], visited: vec![false; program_len] }
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.visited.fill(false);
+    }
+}
+
+
+// Runs the program in lockstep over the input (Pike VM), every thread carries its own capture
+// slots. Takes O(program size * input length) time since each pc is only followed once per position.
+// Unless `anchored`, a new lowest priority thread is started at every position until something
+// matched, as if the pattern began with .*?, so one pass finds the leftmost match at or after start.
+// Positions are byte offsets, the input is decoded one char (or invalid byte) at a time.
+// The program must not contain backreferences.
+pub fn search(program: &Program, haystack: &[u8], start: usize, anchored: bool, semantics: MatchSemantics) -> Option<Slots> {
+    let mut current = ThreadList::new(program.insts.len());
+    let mut next = ThreadList::new(program.insts.len());
+    let mut matched: Option<Slots> = None;
+    let mut slots = vec![None; program.slot_count];
+    let mut stack = vec![];
+
+    let mut pos = start;
+    loop {
+        if matched.is_none() && (pos == start || !anchored) {
+            add_thread(program, &mut current, &mut stack, 0, pos, haystack, &mut slots);
+        }
+
+        // nothing left to run and no new threads will be started
+        if current.threads.is_empty() && (matched.is_some() || anchored) {
+            break;
+        }
+
+        let next_unit = decode(haystack, pos); // None at the end of the input
+        for thread in current.threads.iter_mut() {
+            match &program.insts[thread.pc] {
+                Inst::Match(_) => {
+                    match semantics {
+                        MatchSemantics::LeftmostFirst => {
+                            // everything after this thread has lower priority, drop it
+                            matched = Some(mem::take(&mut thread.slots));
+                            break;
+                        }
+                        MatchSemantics::LeftmostLongest => {
+                            // an earlier start always wins, for the same start a later position is
+                            // longer, and at the same position the first (highest priority) thread wins
+                            if is_better_longest(&thread.slots, matched.as_ref()) {
+                                matched = Some(thread.slots.clone());
+                            }
+                        }
+                    }
+                }
+
+                Inst::Char(node) => {
+                    if let Some((unit, len)) = next_unit {
+                        if match_unit(node, unit) {
+                            add_thread(program, &mut next, &mut stack, thread.pc + 1, pos + len, haystack, &mut thread.slots);
+                        }
+                    }
+                }
+
+                inst => unreachable!("{:?} is never queued as a thread", inst),
+            }
+        }
+
+        mem::swap(&mut current, &mut next);
+        next.clear();
+
+        match next_unit {
+            Some((_, len)) => pos += len,
+            None => break,
+        }
+    }
+
+    matched
+}
+
+
+fn is_better_longest(candidate: &Slots, best: Option<&Slots>) -> bool {
+    match best {
+        None => true,
+        Some(best) => candidate[0] < best[0] || (candidate[0] == best[0] && candidate[1] > best[1]),
+    }
+}
+
+
+// Work left for add_thread, popped in priority order
+enum Frame {
+    Explore(usize),
+    RestoreSlot(usize, Option<usize>), // undo a Save once everything after it has been followed
+}
+
+
+// Follows the epsilon transitions from pc and queues every thread that consumes input or matches.
+// Uses an explicit stack instead of recursion since a chain of Splits is as long as the pattern.
+fn add_thread(program: &Program, list: &mut ThreadList, stack: &mut Vec<Frame>, pc: usize, pos: usize, haystack: &[u8], slots: &mut Slots) {
+    stack.push(Frame::Explore(pc));
+    while let Some(frame) = stack.pop() {
+        let mut pc = match frame {
+            Frame::Explore(pc) => pc,
+            Frame::RestoreSlot(slot, old) => {
+                slots[slot] = old;
+                continue;
+            }
+        };
+
+        // follow the preferred path right away, anything tried later goes on the stack
+        loop {
+            if list.visited[pc] {
+                break;
+            }
+            list.visited[pc] = true;
+
+            match &program.insts[pc] {
+                Inst::Jmp(target) => pc = *target,
+
+                Inst::Split(preferred, other) => {
+                    stack.push(Frame::Explore(*other));
+                    pc = *preferred;
+                }
+
+                Inst::Save(slot) => {
+                    stack.push(Frame::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    pc += 1;
+                }
+
+                Inst::StartOfLine if pos == 0 => pc += 1,
+                Inst::EndOfLine if pos == haystack.len() => pc += 1,
+                Inst::LineStart(terminator) if is_line_start(haystack, pos, *terminator) => pc += 1,
+                Inst::LineEnd(terminator) if is_line_end(haystack, pos, *terminator) => pc += 1,
+                Inst::StartOfLine | Inst::EndOfLine | Inst::LineStart(_) | Inst::LineEnd(_) => break,
+
+                Inst::Backreference(_) => unreachable!("the Pike VM can't run backreferences"),
+
+                Inst::Char(_) | Inst::Match(_) => {
+                    list.threads.push(Thread { pc, slots: slots.clone() });
+                    break;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::compile;
+    use crate::pattern::{RegexAst, Repetition};
+    use crate::regex::max_group_id;
+    use crate::simplify::simplify;
+    use crate::utils::pattern_to_ast;
+
+    // Span of the whole match and of every group, None for groups that didn't participate
+    type Spans = Vec<Option<(usize, usize)>>;
+
+    fn search_spans(pattern: &str, haystack: &str, semantics: MatchSemantics) -> Option<Spans> {
+        let ast = simplify(pattern_to_ast(pattern).unwrap());
+        let program = compile(&ast, max_group_id(&ast) as usize);
+        let slots = search(&program, haystack.as_bytes(), 0, false, semantics)?;
+        Some(slots.chunks(2).map(|slot| Some((slot[0]?, slot[1]?))).collect())
+    }
+
+    fn first(pattern: &str, haystack: &str) -> Option<Spans> {
+        search_spans(pattern, haystack, MatchSemantics::LeftmostFirst)
+    }
+
+    #[test]
+    fn capture_positions() {
+        assert_eq!(first(r"(\d+)-(\d+)", "tel 12-345"), Some(vec![Some((4, 10)), Some((4, 6)), Some((7, 10))]));
+        // the last iteration of a repeated group is the one reported
+        assert_eq!(first("(?:(a)|(b))+", "xab"), Some(vec![Some((1, 3)), Some((1, 2)), Some((2, 3))]));
+        assert_eq!(first("(a)|(b)", "b"), Some(vec![Some((0, 1)), None, Some((0, 1))]));
+        assert_eq!(first("(é+)x", "aééx"), Some(vec![Some((1, 6)), Some((1, 5))]));
+    }
+
+    #[test]
+    fn leftmost_first_preference() {
+        assert_eq!(first("a|ab", "ab"), Some(vec![Some((0, 1))]));
+        assert_eq!(first("ab|a", "ab"), Some(vec![Some((0, 2))]));
+        assert_eq!(first("a+?", "aaa"), Some(vec![Some((0, 1))]));
+        assert_eq!(first("(a+)(a*)", "aaa"), Some(vec![Some((0, 3)), Some((0, 3)), Some((3, 3))]));
+        assert_eq!(first("(a+?)(a*)", "aaa"), Some(vec![Some((0, 3)), Some((0, 1)), Some((1, 3))]));
+        // an earlier start wins over a longer match further right
+        assert_eq!(first("b|abc", "xabc"), Some(vec![Some((1, 4))]));
+    }
+
+    #[test]
+    fn only_the_first_repetition_may_match_nothing() {
+        // like Perl and RE2, see nfa::compile_repeat
+        assert_eq!(first("(?:c*?)*", "c"), Some(vec![Some((0, 0))]));
+        assert_eq!(first("(?:((?:a)*?))*", ""), Some(vec![Some((0, 0)), Some((0, 0))]));
+        assert_eq!(first("(a*)*", "b"), Some(vec![Some((0, 0)), Some((0, 0))]));
+        assert_eq!(first("(a|)*", "aa"), Some(vec![Some((0, 2)), Some((1, 2))]));
+    }
+
+    #[test]
+    fn leftmost_longest() {
+        assert_eq!(search_spans("a|ab", "ab", MatchSemantics::LeftmostLongest), Some(vec![Some((0, 2))]));
+        assert_eq!(search_spans("a+?", "aaa", MatchSemantics::LeftmostLongest), Some(vec![Some((0, 3))]));
+    }
+
+    #[test]
+    fn long_chain_of_optionals() {
+        // every a? is a Split into the next one, following them recursively overflowed the stack
+        // (built directly, parsing a pattern this long is slow in debug builds)
+        let optional_a = RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::Optional);
+        let program = compile(&RegexAst::Concat(vec![optional_a; 30_000]), 0);
+        assert_eq!(search(&program, b"aaa", 0, false, MatchSemantics::LeftmostFirst), Some(vec![Some(0), Some(3)]));
+        assert_eq!(search(&program, b"b", 0, false, MatchSemantics::LeftmostFirst), Some(vec![Some(0), Some(0)]));
+    }
+}
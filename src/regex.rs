@@ -0,0 +1,905 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Index, Range};
+use std::sync::{Arc, Mutex};
+
+use crate::backtrack;
+use crate::dfa::DfaCache;
+use crate::error::Error;
+use crate::literal::{extract_literals, start_bytes, RequiredLiterals};
+use crate::memchr::{memchr, memchr2, memchr3, memmem};
+use crate::matcher::{solve, Budget, CaptureMap};
+use crate::nfa::{compile, Program};
+use crate::pattern::{RegexAst, Repetition};
+use crate::pikevm;
+use crate::replacer::Replacer;
+use crate::reverse::ReverseSearch;
+use crate::simplify::simplify;
+use crate::utf8::unit_len;
+use crate::utils::{pattern_to_ast_with, ParseFlags};
+
+
+/// Default for [`RegexBuilder::size_limit`].
+pub const DEFAULT_SIZE_LIMIT: usize = 1_000_000;
+
+/// Default for [`RegexBuilder::backtrack_limit`].
+pub const DEFAULT_BACKTRACK_LIMIT: usize = 1_000_000;
+
+/// Default for [`RegexBuilder::recursion_limit`].
+pub const DEFAULT_RECURSION_LIMIT: usize = 1_000;
+
+
+// Span of every group in byte offsets into the haystack, None if the group didn't participate
+type Spans = Vec<Option<(usize, usize)>>;
+
+
+/// Which match wins when several matches start at the same leftmost position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchSemantics {
+    /// Perl style: the first match found when trying alternatives left to right and
+    /// repetitions greedy/lazy as written, e.g. `a|ab` finds `a` in `ab`.
+    #[default]
+    LeftmostFirst,
+    /// POSIX style (GNU grep -o, sed): the longest match, e.g. `a|ab` finds `ab` in `ab`.
+    LeftmostLongest,
+}
+
+
+/// Compiles a [`Regex`] with non-default options.
+///
+/// ```
+/// use codecrafters_grep::{MatchSemantics, RegexBuilder};
+///
+/// let re = RegexBuilder::new("a|ab").match_semantics(MatchSemantics::LeftmostLongest).build().unwrap();
+/// assert_eq!(re.find("ab").unwrap().as_str(), "ab");
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    pattern: String,
+    semantics: MatchSemantics,
+    size_limit: usize,
+    backtrack_limit: usize,
+    recursion_limit: usize,
+    multi_line: bool,
+    dot_all: bool,
+    line_terminator: u8,
+}
+
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> RegexBuilder {
+        RegexBuilder {
+            pattern: pattern.to_string(),
+            semantics: MatchSemantics::default(),
+            size_limit: DEFAULT_SIZE_LIMIT,
+            backtrack_limit: DEFAULT_BACKTRACK_LIMIT,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            multi_line: false,
+            dot_all: false,
+            line_terminator: b'\n',
+        }
+    }
+
+    pub fn match_semantics(&mut self, semantics: MatchSemantics) -> &mut RegexBuilder {
+        self.semantics = semantics;
+        self
+    }
+
+    /// Maximum number of NFA instructions the pattern may compile to, bigger patterns
+    /// fail to build with [`Error::SizeLimitExceeded`].
+    pub fn size_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Maximum number of steps the backtracking matcher may take for one search. Only patterns with
+    /// backreferences backtrack, a search that runs out fails with [`Error::BacktrackLimitExceeded`].
+    pub fn backtrack_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.backtrack_limit = limit;
+        self
+    }
+
+    /// Maximum nesting depth of the backtracking matcher, which grows with the length of what a
+    /// repetition matches. Going deeper fails with [`Error::RecursionLimitExceeded`] instead of
+    /// overflowing the stack.
+    pub fn recursion_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.recursion_limit = limit;
+        self
+    }
+
+    /// Makes `^` and `$` match at the start and end of every line instead of only at the start and
+    /// end of the haystack, for haystacks of several `\n` separated lines.
+    ///
+    /// ```
+    /// use codecrafters_grep::RegexBuilder;
+    ///
+    /// let re = RegexBuilder::new("^b$").multi_line(true).build().unwrap();
+    /// assert_eq!(re.find("a\nb\nc").unwrap().range(), 2..3);
+    /// ```
+    pub fn multi_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.multi_line = yes;
+        self
+    }
+
+    /// Makes `.` match `\n` too, as if the pattern started with `(?s)`.
+    ///
+    /// ```
+    /// use codecrafters_grep::RegexBuilder;
+    ///
+    /// let re = RegexBuilder::new("a.b").dot_matches_new_line(true).build().unwrap();
+    /// assert!(re.is_match("a\nb"));
+    /// ```
+    pub fn dot_matches_new_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.dot_all = yes;
+        self
+    }
+
+    /// The byte that ends a line, `\n` by default. `.` doesn't match it (unless
+    /// [`RegexBuilder::dot_matches_new_line`] is set) and in multi-line mode `^` and `$` match next to it.
+    /// Has to be an ASCII byte, `\0` is what `grep -z` uses.
+    ///
+    /// ```
+    /// use codecrafters_grep::RegexBuilder;
+    ///
+    /// let re = RegexBuilder::new("^b.c$").multi_line(true).line_terminator(b'\0').build().unwrap();
+    /// assert_eq!(re.find("a\0b\nc\0d").unwrap().range(), 2..5);
+    /// ```
+    pub fn line_terminator(&mut self, terminator: u8) -> &mut RegexBuilder {
+        assert!(terminator.is_ascii(), "the line terminator has to be an ASCII byte");
+        self.line_terminator = terminator;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, Error> {
+        let flags = ParseFlags { dot_all: self.dot_all, multi_line: self.multi_line, line_terminator: self.line_terminator };
+        let ast = simplify(pattern_to_ast_with(&self.pattern, flags)?);
+        let group_count = max_group_id(&ast) as usize;
+
+        let mut group_names = vec![None; group_count + 1];
+        collect_group_names(&ast, &mut group_names);
+
+        let mut named_groups = HashMap::new();
+        for (group_id, name) in group_names.iter().enumerate() {
+            if let Some(name) = name {
+                if named_groups.insert(name.clone(), group_id).is_some() {
+                    return Err(Error::DuplicateGroupName(name.clone()));
+                }
+            }
+        }
+
+        let program = compile(&ast, group_count);
+        if program.insts.len() > self.size_limit {
+            return Err(Error::SizeLimitExceeded { size: program.insts.len(), limit: self.size_limit });
+        }
+
+        let anchored_start = is_anchored_start(&ast);
+        let literals = extract_literals(&ast);
+        // the lazy DFA can't do backreferences or multi-line anchors, those patterns never use it
+        let uses_dfa = !program.has_backreferences && !program.has_line_anchors;
+        let reverse = if anchored_start || !uses_dfa { None } else { ReverseSearch::new(&ast, &literals) };
+        let dfa_cache = if uses_dfa { Some(Arc::new(Mutex::new(DfaCache::new(&program)))) } else { None };
+
+        Ok(Regex {
+            pattern: self.pattern.clone(),
+            dfa_cache,
+            program,
+            anchored_start,
+            is_literal: matches!(ast, RegexAst::Literal(_) | RegexAst::LiteralString(_)),
+            start_bytes: start_bytes(&ast),
+            literals,
+            reverse: reverse.map(Arc::new),
+            ast,
+            semantics: self.semantics,
+            backtrack_limit: self.backtrack_limit,
+            recursion_limit: self.recursion_limit,
+            group_count,
+            group_names,
+            named_groups: Arc::new(named_groups),
+        })
+    }
+}
+
+
+/// A compiled regular expression.
+///
+/// The pattern is parsed, simplified and compiled to an NFA once in [`Regex::new`], after that the same
+/// `Regex` can be used to search any number of haystacks. All offsets handed out
+/// ([`Match::start`], [`Match::end`], ...) are byte offsets into the haystack.
+///
+/// Patterns with backreferences are matched by backtracking, which can take exponential time.
+/// Such a search gives up once it runs over the limits set on the [`RegexBuilder`], the `try_`
+/// methods report that as an error while the others treat it as no match.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    ast: RegexAst,
+    program: Program,
+    anchored_start: bool, // every match has to start with ^
+    is_literal: bool,     // the pattern is plain text (like every pattern in -F mode), the prefix is the whole match
+    start_bytes: Option<Vec<u8>>, // every match starts with one of these
+    literals: RequiredLiterals,
+    reverse: Option<Arc<ReverseSearch>>, // set if a literal late in the pattern is a better place to start
+    dfa_cache: Option<Arc<Mutex<DfaCache>>>, // shared by clones, whoever holds the lock gets the warm cache. None if the DFA can't run the pattern
+    semantics: MatchSemantics,
+    backtrack_limit: usize,
+    recursion_limit: usize,
+    group_count: usize,
+    group_names: Vec<Option<String>>, // indexed by group id, group 0 never has a name
+    named_groups: Arc<HashMap<String, usize>>,
+}
+
+
+impl Regex {
+    /// Compiles `pattern` with the default options, failing if it isn't a valid pattern.
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    /// The pattern this regex was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn match_semantics(&self) -> MatchSemantics {
+        self.semantics
+    }
+
+    /// The (simplified) AST the matcher runs on.
+    pub fn ast(&self) -> &RegexAst {
+        &self.ast
+    }
+
+    /// The literal text every match has to contain, lines without it are skipped before running the matcher.
+    pub fn required_literals(&self) -> &RequiredLiterals {
+        &self.literals
+    }
+
+    /// Number of groups including the implicit group 0 covering the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.group_count + 1
+    }
+
+    /// The name of every group in order, None for unnamed groups (and always for group 0).
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> + '_ {
+        self.group_names.iter().map(|name| name.as_deref())
+    }
+
+    /// Returns true if the regex matches anywhere in `haystack`.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.is_match_bytes(haystack.as_bytes())
+    }
+
+    /// Like [`Regex::is_match`], but fails if the search ran out of its budget.
+    pub fn try_is_match(&self, haystack: &str) -> Result<bool, Error> {
+        self.try_is_match_bytes(haystack.as_bytes())
+    }
+
+    /// Returns true if the regex matches anywhere in `haystack`, which doesn't have to be valid UTF-8.
+    /// Bytes that aren't part of a valid UTF-8 sequence are only matched by `.` and `\xNN`.
+    pub fn is_match_bytes(&self, haystack: &[u8]) -> bool {
+        self.try_is_match_bytes(haystack).unwrap_or(false)
+    }
+
+    /// Like [`Regex::is_match_bytes`], but fails if the search ran out of its budget.
+    pub fn try_is_match_bytes(&self, haystack: &[u8]) -> Result<bool, Error> {
+        if !self.may_match(haystack) {
+            return Ok(false);
+        }
+        // the literal scan already found the whole pattern
+        if self.is_literal {
+            return Ok(true);
+        }
+
+        // Only a yes/no is needed so no captures, which is what the lazy DFA is good at
+        if let Some(dfa_cache) = &self.dfa_cache {
+            if let Some(is_match) = self.reverse.as_ref().and_then(|reverse| reverse.is_match(haystack)) {
+                return Ok(is_match);
+            }
+            return Ok(DfaCache::with_shared(dfa_cache, &self.program, DfaCache::new, |cache| cache.is_match(&self.program, haystack)));
+        }
+
+        Ok(self.search_at(haystack, 0)?.is_some())
+    }
+
+    /// Returns the leftmost match in `haystack`, if any.
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.try_find(haystack).ok().flatten()
+    }
+
+    /// Like [`Regex::find`], but fails if the search ran out of its budget.
+    pub fn try_find<'h>(&self, haystack: &'h str) -> Result<Option<Match<'h>>, Error> {
+        Ok(self.search_at(haystack.as_bytes(), 0)?.and_then(|spans| group_match(haystack, &spans, 0)))
+    }
+
+    /// Byte range of the leftmost match in `haystack`, which doesn't have to be valid UTF-8.
+    pub fn find_bytes(&self, haystack: &[u8]) -> Option<Range<usize>> {
+        let spans = self.search_at(haystack, 0).ok()??;
+        spans[0].map(|(start, end)| start..end)
+    }
+
+    /// Iterates over all successive non-overlapping matches in `haystack`.
+    pub fn find_iter<'r, 'h>(&'r self, haystack: &'h str) -> FindMatches<'r, 'h> {
+        FindMatches { state: SearchState::new(self, haystack.as_bytes()), haystack }
+    }
+
+    /// Like [`Regex::find_iter`], but yields an error and stops if a search ran out of its budget.
+    pub fn try_find_iter<'r, 'h>(&'r self, haystack: &'h str) -> TryFindMatches<'r, 'h> {
+        TryFindMatches { state: SearchState::new(self, haystack.as_bytes()), haystack }
+    }
+
+    /// Iterates over the byte ranges of all successive non-overlapping matches in `haystack`, which
+    /// doesn't have to be valid UTF-8. Yields an error and stops if a search ran out of its budget.
+    pub fn try_find_iter_bytes<'r, 'h>(&'r self, haystack: &'h [u8]) -> TryFindByteMatches<'r, 'h> {
+        TryFindByteMatches(SearchState::new(self, haystack))
+    }
+
+    /// Returns the groups of the leftmost match in `haystack`, if any.
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        self.try_captures(haystack).ok().flatten()
+    }
+
+    /// Like [`Regex::captures`], but fails if the search ran out of its budget.
+    pub fn try_captures<'h>(&self, haystack: &'h str) -> Result<Option<Captures<'h>>, Error> {
+        Ok(self.search_at(haystack.as_bytes(), 0)?.map(|spans| self.captures_from(haystack, spans)))
+    }
+
+    /// Iterates over the groups of all successive non-overlapping matches in `haystack`.
+    pub fn captures_iter<'r, 'h>(&'r self, haystack: &'h str) -> CaptureMatches<'r, 'h> {
+        CaptureMatches { state: SearchState::new(self, haystack.as_bytes()), haystack }
+    }
+
+    /// Iterates over the substrings of `haystack` between the matches of this regex.
+    pub fn split<'r, 'h>(&'r self, haystack: &'h str) -> Split<'r, 'h> {
+        Split {
+            matches: self.find_iter(haystack),
+            haystack,
+            last_end: 0,
+            done: false,
+        }
+    }
+
+    /// Replaces the leftmost match with the replacement, see [`Replacer`] for what `$1` etc. mean.
+    pub fn replace<'h, R: Replacer>(&self, haystack: &'h str, rep: R) -> Cow<'h, str> {
+        self.replacen(haystack, 1, rep)
+    }
+
+    /// Replaces every non-overlapping match with the replacement.
+    pub fn replace_all<'h, R: Replacer>(&self, haystack: &'h str, rep: R) -> Cow<'h, str> {
+        self.replacen(haystack, 0, rep)
+    }
+
+    /// Replaces at most `limit` matches, a limit of 0 replaces all of them.
+    pub fn replacen<'h, R: Replacer>(&self, haystack: &'h str, limit: usize, mut rep: R) -> Cow<'h, str> {
+        let mut result = String::new();
+        let mut last_end = 0;
+        let mut replaced = 0;
+
+        for caps in self.captures_iter(haystack) {
+            let whole = caps.get(0).unwrap();
+            result.push_str(&haystack[last_end..whole.start()]);
+            rep.replace_append(&caps, &mut result);
+            last_end = whole.end();
+
+            replaced += 1;
+            if replaced == limit {
+                break;
+            }
+        }
+
+        if replaced == 0 {
+            return Cow::Borrowed(haystack);
+        }
+
+        result.push_str(&haystack[last_end..]);
+        Cow::Owned(result)
+    }
+
+    // Leftmost match starting at or after the byte offset `start`, which has to be at a char
+    // boundary. Fails only when backtracking runs out of its budget.
+    fn search_at(&self, haystack: &[u8], start: usize) -> Result<Option<Spans>, Error> {
+        let rest = &haystack[start..];
+        if !self.may_match(rest) {
+            return Ok(None);
+        }
+
+        // no match can start before the first occurrence of the prefix, or if there is
+        // none before the first byte a match can start with
+        let prefix = self.literals.prefix.as_bytes();
+        let skipped = if self.anchored_start {
+            Some(0)
+        } else if !prefix.is_empty() {
+            memmem(rest, prefix)
+        } else {
+            self.start_bytes.as_ref().map_or(Some(0), |bytes| find_any_byte(rest, bytes))
+        };
+        let Some(skipped) = skipped else {
+            return Ok(None);
+        };
+        let start = start + skipped;
+
+        if self.is_literal {
+            return Ok(Some(vec![Some((start, start + prefix.len()))]));
+        }
+
+        // Backreferences need the backtracking solve(), everything else runs on the Pike VM
+        // which finds the leftmost match in a single pass over the input
+        if !self.program.has_backreferences {
+            let slots = pikevm::search(&self.program, haystack, start, self.anchored_start, self.semantics);
+            return Ok(slots.map(slots_to_spans));
+        }
+
+        // one budget for the whole search, not per start position
+        let mut budget = Budget::new(self.backtrack_limit, self.recursion_limit);
+
+        // The bounded backtracker memoizes what doesn't depend on captures, but only finds leftmost-first
+        // matches and needs a bit per (pc, position). Everything else is left to solve().
+        let fits_visited = self.program.insts.len() * (haystack.len() + 1) <= backtrack::MAX_VISITED;
+        if self.semantics == MatchSemantics::LeftmostFirst && fits_visited {
+            let slots = backtrack::search(&self.program, haystack, start, self.anchored_start, &mut budget)?;
+            return Ok(slots.map(slots_to_spans));
+        }
+
+        // a pattern starting with ^ can only match at position 0
+        if self.anchored_start {
+            return if start == 0 { self.backtrack_at(haystack, 0, &mut budget) } else { Ok(None) };
+        }
+
+        let mut start_pos = start;
+        while start_pos <= haystack.len() {
+            if haystack[start_pos..].starts_with(prefix) {
+                if let Some(spans) = self.backtrack_at(haystack, start_pos, &mut budget)? {
+                    return Ok(Some(spans));
+                }
+            }
+            start_pos += unit_len(haystack, start_pos);
+        }
+        Ok(None)
+    }
+
+    // False for patterns the lazy DFA can't run, see RegexBuilder::build
+    pub(crate) fn uses_dfa(&self) -> bool {
+        self.dfa_cache.is_some()
+    }
+
+    // Cheap substring check, false if the haystack can't contain a match
+    pub(crate) fn may_match(&self, haystack: &[u8]) -> bool {
+        self.literals.best().map_or(true, |literal| memmem(haystack, literal.as_bytes()).is_some())
+    }
+
+    fn captures_from<'h>(&self, haystack: &'h str, spans: Spans) -> Captures<'h> {
+        Captures { haystack, spans, named_groups: Arc::clone(&self.named_groups) }
+    }
+
+    // Match starting exactly at start_pos, found with the backtracking solve()
+    fn backtrack_at(&self, haystack: &[u8], start_pos: usize, budget: &mut Budget) -> Result<Option<Spans>, Error> {
+        let paths = solve(haystack, &self.ast, start_pos, &CaptureMap::new(), budget)?;
+
+        // paths come in order of preference, the first one is the leftmost-first match
+        // and the first one among the longest is the leftmost-longest match
+        let chosen = match self.semantics {
+            MatchSemantics::LeftmostFirst => paths.into_iter().next(),
+            MatchSemantics::LeftmostLongest => {
+                let longest = paths.iter().map(|(end_pos, _)| *end_pos).max();
+                paths.into_iter().find(|(end_pos, _)| Some(*end_pos) == longest)
+            }
+        };
+
+        let Some((end_pos, captures)) = chosen else {
+            return Ok(None);
+        };
+        let mut spans = vec![Some((start_pos, end_pos))];
+        for group_id in 1..=self.group_count {
+            spans.push(captures.get(&(group_id as u32)).copied());
+        }
+        Ok(Some(spans))
+    }
+}
+
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+
+// First byte that is one of `bytes` (at most 3 of them, see literal::start_bytes)
+fn find_any_byte(haystack: &[u8], bytes: &[u8]) -> Option<usize> {
+    match *bytes {
+        [byte] => memchr(byte, haystack),
+        [byte1, byte2] => memchr2(byte1, byte2, haystack),
+        [byte1, byte2, byte3] => memchr3(byte1, byte2, byte3, haystack),
+        _ => Some(0),
+    }
+}
+
+
+// The capture slots of a match as (start, end) spans per group
+fn slots_to_spans(slots: pikevm::Slots) -> Spans {
+    slots.chunks(2).map(|slot| Some((slot[0]?, slot[1]?))).collect()
+}
+
+
+pub(crate) fn max_group_id(node: &RegexAst) -> u32 {
+    match node {
+        RegexAst::Concat(nodes) | RegexAst::Alternate(nodes) => nodes.iter().map(max_group_id).max().unwrap_or(0),
+        RegexAst::Repeat(node, _) => max_group_id(node),
+        RegexAst::CaptureGroup(group_id, _, node) => (*group_id).max(max_group_id(node)),
+        _ => 0,
+    }
+}
+
+
+// True if every match of the node has to begin with ^
+fn is_anchored_start(node: &RegexAst) -> bool {
+    match node {
+        RegexAst::StartOfLine => true,
+        RegexAst::Concat(nodes) => nodes.first().is_some_and(is_anchored_start),
+        RegexAst::Alternate(nodes) => nodes.iter().all(is_anchored_start),
+        RegexAst::CaptureGroup(_, _, node) => is_anchored_start(node),
+        RegexAst::Repeat(node, Repetition::Plus | Repetition::LazyPlus) => is_anchored_start(node),
+        _ => false,
+    }
+}
+
+
+fn collect_group_names(node: &RegexAst, group_names: &mut Vec<Option<String>>) {
+    match node {
+        RegexAst::Concat(nodes) | RegexAst::Alternate(nodes) => {
+            for node in nodes {
+                collect_group_names(node, group_names);
+            }
+        }
+        RegexAst::Repeat(node, _) => collect_group_names(node, group_names),
+        RegexAst::CaptureGroup(group_id, name, node) => {
+            group_names[*group_id as usize] = name.clone();
+            collect_group_names(node, group_names);
+        }
+        _ => {}
+    }
+}
+
+
+// The match of one group, for haystacks that are valid UTF-8 every span is on char boundaries
+fn group_match<'h>(haystack: &'h str, spans: &Spans, group: usize) -> Option<Match<'h>> {
+    let (start, end) = spans.get(group).copied().flatten()?;
+    Some(Match { haystack, start, end })
+}
+
+
+/// A single match of a regex in a haystack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'h> {
+    haystack: &'h str,
+    start: usize,
+    end: usize,
+}
+
+
+impl<'h> Match<'h> {
+    /// Byte offset where the match starts.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset right after the end of the match.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The matched text.
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.start..self.end]
+    }
+}
+
+
+/// The groups of a single match, group 0 is always the whole match.
+///
+/// ```
+/// use codecrafters_grep::Regex;
+///
+/// let re = Regex::new(r"user_id=(?P<id>\d+) took (\d+)ms").unwrap();
+/// let caps = re.captures("GET / user_id=42 took 17ms").unwrap();
+/// assert_eq!(&caps["id"], "42");
+/// assert_eq!(caps.get(2).unwrap().range(), 22..24);
+/// assert_eq!(caps.iter().count(), 3);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Captures<'h> {
+    haystack: &'h str,
+    spans: Vec<Option<(usize, usize)>>, // byte offsets
+    named_groups: Arc<HashMap<String, usize>>,
+}
+
+
+impl<'h> Captures<'h> {
+    /// The match of group `i`, None if the group doesn't exist or didn't take part in the match.
+    pub fn get(&self, i: usize) -> Option<Match<'h>> {
+        let (start, end) = self.spans.get(i).copied().flatten()?;
+        Some(Match {
+            haystack: self.haystack,
+            start,
+            end,
+        })
+    }
+
+    /// The match of the group called `name`, None if there is no such group or it didn't match.
+    pub fn name(&self, name: &str) -> Option<Match<'h>> {
+        self.get(*self.named_groups.get(name)?)
+    }
+
+    /// Iterates over every group in order, starting with group 0.
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// Number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+
+impl Index<usize> for Captures<'_> {
+    type Output = str;
+
+    // Panics when the group didn't match, like the regex crate
+    fn index(&self, i: usize) -> &str {
+        self.get(i)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group at index '{}'", i))
+    }
+}
+
+
+impl Index<&str> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, name: &str) -> &str {
+        self.name(name)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group named '{}'", name))
+    }
+}
+
+
+// Shared state of the iterators, walks the haystack match after match
+struct SearchState<'r, 'h> {
+    regex: &'r Regex,
+    haystack: &'h [u8],
+    next_start: usize,
+    last_match_end: Option<usize>,
+}
+
+
+impl<'r, 'h> SearchState<'r, 'h> {
+    fn new(regex: &'r Regex, haystack: &'h [u8]) -> SearchState<'r, 'h> {
+        SearchState {
+            regex,
+            haystack,
+            next_start: 0,
+            last_match_end: None,
+        }
+    }
+
+    // The next match, after an error the search is over
+    fn next_spans(&mut self) -> Option<Result<Spans, Error>> {
+        loop {
+            if self.next_start > self.haystack.len() {
+                return None;
+            }
+
+            let spans = match self.regex.search_at(self.haystack, self.next_start) {
+                Ok(Some(spans)) => spans,
+                result => {
+                    self.next_start = self.haystack.len() + 1;
+                    return result.transpose();
+                }
+            };
+
+            let (start, end) = spans[0].unwrap();
+
+            // an empty match right where the previous match ended isn't reported,
+            // the search goes on from the next char (not byte)
+            if start == end && self.last_match_end == Some(end) {
+                self.next_start = start + unit_len(self.haystack, start);
+                continue;
+            }
+
+            self.next_start = if start == end { end + unit_len(self.haystack, end) } else { end };
+            self.last_match_end = Some(end);
+            return Some(Ok(spans));
+        }
+    }
+}
+
+
+/// Iterator over matches, created by [`Regex::find_iter`].
+pub struct FindMatches<'r, 'h> {
+    state: SearchState<'r, 'h>,
+    haystack: &'h str,
+}
+
+
+impl<'h> Iterator for FindMatches<'_, 'h> {
+    type Item = Match<'h>;
+
+    fn next(&mut self) -> Option<Match<'h>> {
+        let spans = self.state.next_spans()?.ok()?;
+        group_match(self.haystack, &spans, 0)
+    }
+}
+
+
+/// Iterator over matches that reports running out of budget, created by [`Regex::try_find_iter`].
+pub struct TryFindMatches<'r, 'h> {
+    state: SearchState<'r, 'h>,
+    haystack: &'h str,
+}
+
+
+impl<'h> Iterator for TryFindMatches<'_, 'h> {
+    type Item = Result<Match<'h>, Error>;
+
+    fn next(&mut self) -> Option<Result<Match<'h>, Error>> {
+        Some(self.state.next_spans()?.map(|spans| group_match(self.haystack, &spans, 0).unwrap()))
+    }
+}
+
+
+/// Iterator over the byte ranges of matches in a haystack that doesn't have to be valid UTF-8,
+/// created by [`Regex::try_find_iter_bytes`].
+pub struct TryFindByteMatches<'r, 'h>(SearchState<'r, 'h>);
+
+
+impl Iterator for TryFindByteMatches<'_, '_> {
+    type Item = Result<Range<usize>, Error>;
+
+    fn next(&mut self) -> Option<Result<Range<usize>, Error>> {
+        Some(self.0.next_spans()?.map(|spans| {
+            let (start, end) = spans[0].unwrap();
+            start..end
+        }))
+    }
+}
+
+
+/// Iterator over the groups of every match, created by [`Regex::captures_iter`].
+pub struct CaptureMatches<'r, 'h> {
+    state: SearchState<'r, 'h>,
+    haystack: &'h str,
+}
+
+
+impl<'h> Iterator for CaptureMatches<'_, 'h> {
+    type Item = Captures<'h>;
+
+    fn next(&mut self) -> Option<Captures<'h>> {
+        let spans = self.state.next_spans()?.ok()?;
+        Some(self.state.regex.captures_from(self.haystack, spans))
+    }
+}
+
+
+/// Iterator over the text between matches, created by [`Regex::split`].
+pub struct Split<'r, 'h> {
+    matches: FindMatches<'r, 'h>,
+    haystack: &'h str,
+    last_end: usize,
+    done: bool,
+}
+
+
+impl<'h> Iterator for Split<'_, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        if self.done {
+            return None;
+        }
+
+        match self.matches.next() {
+            Some(m) => {
+                let piece = &self.haystack[self.last_end..m.start()];
+                self.last_end = m.end();
+                Some(piece)
+            }
+            None => {
+                self.done = true;
+                Some(&self.haystack[self.last_end..])
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backtrack_limit_exceeded() {
+        // (a|aa)+ splits a run of a's in exponentially many ways, each one is tried against \1
+        let re = RegexBuilder::new(r"(a|aa)+\1c").backtrack_limit(1000).build().unwrap();
+        let haystack = format!("{}bc", "a".repeat(30));
+        assert_eq!(re.try_is_match(&haystack), Err(Error::BacktrackLimitExceeded(1000)));
+        assert_eq!(re.try_find(&haystack), Err(Error::BacktrackLimitExceeded(1000)));
+        // the non-try versions treat it as no match
+        assert!(!re.is_match(&haystack));
+        assert!(re.try_is_match("aaaac").unwrap());
+    }
+
+    #[test]
+    fn size_limit_exceeded() {
+        let pattern = "abc".repeat(100);
+        assert!(matches!(
+            RegexBuilder::new(&pattern).size_limit(50).build(),
+            Err(Error::SizeLimitExceeded { limit: 50, .. })
+        ));
+        assert!(RegexBuilder::new(&pattern).size_limit(1000).build().is_ok());
+    }
+
+    #[test]
+    fn recursion_limit_exceeded() {
+        // leftmost-longest backreference patterns run on solve(), which nests once per b taken by b*
+        let re = RegexBuilder::new(r"(a)\1b*")
+            .match_semantics(MatchSemantics::LeftmostLongest)
+            .recursion_limit(50)
+            .build()
+            .unwrap();
+        let haystack = format!("aa{}", "b".repeat(200));
+        assert_eq!(re.try_find(&haystack), Err(Error::RecursionLimitExceeded(50)));
+        assert_eq!(re.try_find("aabbb").unwrap().unwrap().range(), 0..5);
+    }
+
+    #[test]
+    fn hex_escape_matches_a_lone_byte() {
+        let re = Regex::new(r"\xff").unwrap();
+        assert!(re.is_match_bytes(b"a\xffb"));
+        assert_eq!(re.find_bytes(b"a\xffb"), Some(1..2));
+        assert!(!re.is_match_bytes("ÿ".as_bytes()));
+    }
+
+    #[test]
+    fn hex_escape_does_not_match_inside_a_char() {
+        // é is c3 a9, neither byte is a char of its own
+        assert!(!Regex::new(r"\xc3").unwrap().is_match_bytes("café".as_bytes()));
+        assert!(!Regex::new(r"\xa9").unwrap().is_match_bytes("café".as_bytes()));
+        assert!(Regex::new(r"\xa9").unwrap().is_match_bytes(b"caf\xa9"));
+        assert!(!Regex::new(r"f\xc3").unwrap().is_match_bytes("café".as_bytes()));
+    }
+
+    #[test]
+    fn dot_matches_an_invalid_byte() {
+        let re = Regex::new("a.b").unwrap();
+        assert_eq!(re.find_bytes(b"xa\xffby"), Some(1..4));
+        // a truncated char is one invalid byte, not a char that swallows what follows
+        assert_eq!(re.find_bytes(b"a\xc3b"), Some(0..3));
+        assert_eq!(Regex::new("a..b").unwrap().find_bytes(b"a\xc3b"), None);
+    }
+
+    #[test]
+    fn invalid_hex_escapes() {
+        assert_eq!(Regex::new(r"\x4").unwrap_err(), Error::InvalidHexEscape(0));
+        assert_eq!(Regex::new(r"\xzz").unwrap_err(), Error::InvalidHexEscape(0));
+        assert_eq!(Regex::new(r"ab\x4g").unwrap_err(), Error::InvalidHexEscape(2));
+    }
+}
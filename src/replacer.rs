@@ -0,0 +1,86 @@
+use crate::regex::Captures;
+
+
+/// Something that can produce the replacement text for a match, used by
+/// [`Regex::replace`](crate::Regex::replace) and friends.
+///
+/// For strings, `$N` and `${N}` are replaced with the text of group N, `$name` and
+/// `${name}` with the group called name (empty if the group didn't match) and `$$`
+/// is a literal `$`. Closures get the groups of each match.
+pub trait Replacer {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String);
+}
+
+
+impl Replacer for &str {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        expand(self, caps, dst);
+    }
+}
+
+
+impl Replacer for String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        expand(self, caps, dst);
+    }
+}
+
+
+impl Replacer for &String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        expand(self, caps, dst);
+    }
+}
+
+
+impl<F, T> Replacer for F
+where
+    F: FnMut(&Captures<'_>) -> T,
+    T: AsRef<str>,
+{
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        dst.push_str((*self)(caps).as_ref());
+    }
+}
+
+
+// Writes the replacement into dst with $N / ${N} / $name / ${name} / $$ substituted
+fn expand(replacement: &str, caps: &Captures<'_>, dst: &mut String) {
+    let chars: Vec<char> = replacement.chars().collect();
+    let mut ind = 0;
+
+    while ind < chars.len() {
+        if chars[ind] != '$' || ind + 1 == chars.len() {
+            dst.push(chars[ind]);
+            ind += 1;
+            continue;
+        }
+
+        if chars[ind + 1] == '$' {
+            dst.push('$');
+            ind += 2;
+            continue;
+        }
+
+        let braced = chars[ind + 1] == '{';
+        let group_start = if braced { ind + 2 } else { ind + 1 };
+        let group: String = chars[group_start..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '_').collect();
+        let group_end = group_start + group.len();
+
+        if group.is_empty() || (braced && chars.get(group_end) != Some(&'}')) {
+            // not a group reference, keep the '$' as is
+            dst.push('$');
+            ind += 1;
+            continue;
+        }
+
+        let group_match = match group.parse::<usize>() {
+            Ok(group_id) => caps.get(group_id),
+            Err(_) => caps.name(&group),
+        };
+        if let Some(m) = group_match {
+            dst.push_str(m.as_str());
+        }
+        ind = if braced { group_end + 1 } else { group_end };
+    }
+}
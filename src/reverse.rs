@@ -0,0 +1,220 @@
+use std::sync::Mutex;
+
+use crate::dfa::DfaCache;
+use crate::literal::RequiredLiterals;
+use crate::memchr::memmem;
+use crate::nfa::{compile, Program};
+use crate::pattern::RegexAst;
+
+
+// Shorter literals occur too often for starting at every occurrence to pay off
+const MIN_LITERAL_LEN: usize = 3;
+
+
+// Checks for a match starting from the occurrences of a literal that is required late in the pattern,
+// like the suffix of \w+@example\.com. The part of the pattern before the literal is compiled reversed
+// and run backwards from the occurrence, so only the text around it is looked at instead of the whole
+// haystack.
+//
+// Reverse suffix: every match ends with the literal, the whole pattern runs backwards from its end.
+// Reverse inner: the pattern is `before literal after` at the top level, `before` runs backwards from the
+// start of the literal and `after` forwards from its end.
+//
+// Only answers whether there is a match. The run backwards does find where a match ending at an occurrence
+// starts, but a match starting even earlier may end at a later occurrence, so find() still needs the
+// forward search.
+#[derive(Debug)]
+pub struct ReverseSearch {
+    literal: String,
+    reversed: Program,
+    reversed_from_end: bool,  // suffix: run from the end of the literal, inner: from its start
+    forward: Option<Program>, // inner only, what comes after the literal
+    reversed_cache: Mutex<DfaCache>,
+    forward_cache: Mutex<DfaCache>,
+}
+
+
+impl ReverseSearch {
+    // None if the pattern has no literal that is worth starting from. The pattern must not use
+    // backreferences or multi-line anchors and must not start with a literal prefix (the forward search jumps to that already).
+    pub fn new(ast: &RegexAst, literals: &RequiredLiterals) -> Option<ReverseSearch> {
+        if !literals.prefix.is_empty() {
+            return None;
+        }
+
+        let inner = inner_literal(ast).filter(|(_, literal, _)| literal.len() > literals.suffix.len());
+        let (literal, reversed, reversed_from_end, forward) = match inner {
+            Some((before, literal, after)) if literal.len() >= MIN_LITERAL_LEN => {
+                let before = reverse_ast(&RegexAst::Concat(before.to_vec()));
+                (literal, before, false, Some(compile(&without_groups(&RegexAst::Concat(after.to_vec())), 0)))
+            }
+            _ if literals.suffix.len() >= MIN_LITERAL_LEN => (literals.suffix.clone(), reverse_ast(ast), true, None),
+            _ => return None,
+        };
+
+        let reversed = compile(&reversed, 0);
+        let forward_cache = Mutex::new(DfaCache::anchored(forward.as_ref().unwrap_or(&reversed)));
+        Some(ReverseSearch {
+            literal,
+            reversed_cache: Mutex::new(DfaCache::anchored(&reversed)),
+            reversed,
+            reversed_from_end,
+            forward,
+            forward_cache,
+        })
+    }
+
+    // Whether the pattern matches anywhere in the haystack. None if so many occurrences had to be looked
+    // at that reading the haystack once from the start would have been cheaper.
+    pub fn is_match(&self, haystack: &[u8]) -> Option<bool> {
+        let literal = self.literal.as_bytes();
+        // the units read for all occurrences together, at most what a forward search reads
+        let mut budget = haystack.len();
+
+        let mut from = 0;
+        while let Some(offset) = memmem(&haystack[from..], literal) {
+            let start = from + offset;
+            let end = start + literal.len();
+
+            let reversed_pos = if self.reversed_from_end { end } else { start };
+            let mut is_match = DfaCache::with_shared(&self.reversed_cache, &self.reversed, DfaCache::anchored, |cache| {
+                cache.is_match_at(&self.reversed, haystack, reversed_pos, true, &mut budget)
+            })?;
+            if let (true, Some(forward)) = (is_match, &self.forward) {
+                is_match = DfaCache::with_shared(&self.forward_cache, forward, DfaCache::anchored, |cache| {
+                    cache.is_match_at(forward, haystack, end, false, &mut budget)
+                })?;
+            }
+            if is_match {
+                return Some(true);
+            }
+
+            // occurrences may overlap, the literal always starts with a whole char
+            from = start + 1;
+        }
+        Some(false)
+    }
+}
+
+
+// The longest literal that is a direct part of a top-level concatenation, with the parts before and after
+// it. Not the first part, that would be a prefix.
+fn inner_literal(ast: &RegexAst) -> Option<(&[RegexAst], String, &[RegexAst])> {
+    let RegexAst::Concat(nodes) = ast else {
+        return None;
+    };
+
+    let (ind, literal) = nodes
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter_map(|(ind, node)| match node {
+            RegexAst::Literal(c) => Some((ind, c.to_string())),
+            RegexAst::LiteralString(text) => Some((ind, text.clone())),
+            _ => None,
+        })
+        .max_by_key(|(ind, literal)| (literal.len(), usize::MAX - ind))?;
+
+    Some((&nodes[..ind], literal, &nodes[ind + 1..]))
+}
+
+
+// The pattern that matches the reversed text of every match, read backwards ^ turns into $ and the other
+// way round. Groups are dropped, only whether there is a match matters.
+fn reverse_ast(node: &RegexAst) -> RegexAst {
+    match node {
+        RegexAst::Concat(nodes) => RegexAst::Concat(nodes.iter().rev().map(reverse_ast).collect()),
+        RegexAst::Alternate(nodes) => RegexAst::Alternate(nodes.iter().map(reverse_ast).collect()),
+        RegexAst::Repeat(node, repetition) => RegexAst::Repeat(Box::new(reverse_ast(node)), *repetition),
+        RegexAst::CaptureGroup(_, _, node) => reverse_ast(node),
+        RegexAst::LiteralString(text) => RegexAst::LiteralString(text.chars().rev().collect()),
+        RegexAst::StartOfLine => RegexAst::EndOfLine,
+        RegexAst::EndOfLine => RegexAst::StartOfLine,
+        RegexAst::LineStart(terminator) => RegexAst::LineEnd(*terminator),
+        RegexAst::LineEnd(terminator) => RegexAst::LineStart(*terminator),
+        other => other.clone(),
+    }
+}
+
+
+fn without_groups(node: &RegexAst) -> RegexAst {
+    match node {
+        RegexAst::Concat(nodes) => RegexAst::Concat(nodes.iter().map(without_groups).collect()),
+        RegexAst::Alternate(nodes) => RegexAst::Alternate(nodes.iter().map(without_groups).collect()),
+        RegexAst::Repeat(node, repetition) => RegexAst::Repeat(Box::new(without_groups(node)), *repetition),
+        RegexAst::CaptureGroup(_, _, node) => without_groups(node),
+        other => other.clone(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::literal::extract_literals;
+    use crate::regex::Regex;
+    use crate::simplify::simplify;
+    use crate::utils::pattern_to_ast;
+
+    fn reverse_search(pattern: &str) -> ReverseSearch {
+        let ast = simplify(pattern_to_ast(pattern).unwrap());
+        ReverseSearch::new(&ast, &extract_literals(&ast)).expect("pattern has a literal to start from")
+    }
+
+    // The reverse search has to agree with the forward one whenever it gives an answer
+    fn reverse_is_match(pattern: &str, haystack: &str) -> Option<bool> {
+        let reverse = reverse_search(pattern).is_match(haystack.as_bytes());
+        if let Some(is_match) = reverse {
+            let forward = Regex::new(pattern).unwrap();
+            assert_eq!(is_match, forward.find(haystack).is_some(), "{} on {:?}", pattern, haystack);
+            assert_eq!(is_match, forward.is_match(haystack), "{} on {:?}", pattern, haystack);
+        }
+        reverse
+    }
+
+    #[test]
+    fn suffix_with_overlapping_occurrences() {
+        assert_eq!(reverse_search(r"\daaa").literal, "aaa");
+        // aaa occurs at every offset of the run, only the one after the digit matches
+        assert_eq!(reverse_is_match(r"\daaa", "some text before aaaaa9aaa"), Some(true));
+        assert_eq!(reverse_is_match(r"\daaa", "9aaaaaa and some text after"), Some(true));
+        assert_eq!(reverse_is_match(r"\daaa", "some text before aaaaaa9aa"), Some(false));
+        assert_eq!(reverse_is_match(r"\d(?:ab)+aba", "some text x ababa 1ababa"), Some(true));
+    }
+
+    #[test]
+    fn end_anchored_suffix() {
+        assert_eq!(reverse_is_match(r"\w+\.com$", "a.com and b.org"), Some(false));
+        assert_eq!(reverse_is_match(r"\w+\.com$", "mail a.com.com"), Some(true));
+        assert_eq!(reverse_is_match(r"\w+\.com$", "x.com"), Some(true));
+        assert_eq!(reverse_is_match(r"\w+\.com$", ".com"), Some(false));
+    }
+
+    #[test]
+    fn multibyte_chars_before_the_literal() {
+        assert_eq!(reverse_is_match(r"\wé+xyz", "caféxyz"), Some(true));
+        assert_eq!(reverse_is_match(r"\wé+xyz", "caféééxyz"), Some(true));
+        assert_eq!(reverse_is_match(r"\wé+xyz", "éxyz cafexyz"), Some(false));
+        // . has to take all of é when read backwards, not just its last byte
+        assert_eq!(reverse_is_match(r"\d.xyz", "1éxyz"), Some(true));
+        assert_eq!(reverse_is_match(r"\d..xyz", "1éxyz"), Some(false));
+    }
+
+    #[test]
+    fn inner_literal() {
+        let search = reverse_search(r"\d+-hello-\d+");
+        assert!(search.forward.is_some());
+        assert_eq!(reverse_is_match(r"\d+-hello-\d+", "12-hello-x 3-hello-4"), Some(true));
+        assert_eq!(reverse_is_match(r"\d+-hello-\d+", "12-hello-x -hello-4"), Some(false));
+    }
+
+    #[test]
+    fn gives_up_once_the_budget_is_spent() {
+        // every offset is an occurrence that has to be read backwards, more than one forward pass
+        let haystack = "a".repeat(1000);
+        assert_eq!(reverse_is_match(r"\daaa", &haystack), None);
+        // the regex falls back to the forward search
+        assert!(!Regex::new(r"\daaa").unwrap().is_match(&haystack));
+        assert!(Regex::new(r"\daaa").unwrap().is_match(&format!("{}7aaa", haystack)));
+    }
+}
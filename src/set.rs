@@ -0,0 +1,182 @@
+use std::sync::{Arc, Mutex};
+
+use crate::dfa::DfaCache;
+use crate::error::Error;
+use crate::nfa::{compile_set, Program};
+use crate::pattern::RegexAst;
+use crate::regex::Regex;
+
+
+/// Several regexes searched in one pass, reporting which of them match.
+///
+/// ```
+/// use codecrafters_grep::RegexSet;
+///
+/// let set = RegexSet::new(["ERROR", r"user_id=\d+", "^WARN"]).unwrap();
+/// let matches = set.matches("ERROR: user_id=42 logged out");
+/// assert_eq!(matches.iter().collect::<Vec<_>>(), vec![0, 1]);
+/// assert!(!set.is_match("INFO: all good"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexSet {
+    regexes: Vec<Regex>,
+    combined: Vec<usize>, // Match(i) in the program is regexes[combined[i]]
+    program: Option<CombinedProgram>, // None if no regex can run on the lazy DFA
+}
+
+
+// Every regex the lazy DFA can run compiled into one program
+#[derive(Debug, Clone)]
+struct CombinedProgram {
+    program: Program,
+    dfa_cache: Arc<Mutex<DfaCache>>,
+}
+
+
+impl RegexSet {
+    /// Compiles every pattern with the default options, failing on the first invalid one.
+    pub fn new<I, S>(patterns: I) -> Result<RegexSet, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let regexes = patterns.into_iter().map(|pattern| Regex::new(pattern.as_ref())).collect::<Result<_, _>>()?;
+        Ok(RegexSet::from_regexes(regexes))
+    }
+
+    /// Builds a set out of already compiled regexes, so each keeps the options it was built with.
+    pub fn from_regexes(regexes: Vec<Regex>) -> RegexSet {
+        // backreferences and multi-line anchors need the other matchers, those regexes are run on their own
+        let combined: Vec<usize> = (0..regexes.len()).filter(|&index| regexes[index].uses_dfa()).collect();
+        let program = if combined.is_empty() {
+            None
+        } else {
+            let asts: Vec<RegexAst> = combined.iter().map(|&index| regexes[index].ast().clone()).collect();
+            let program = compile_set(&asts);
+            Some(CombinedProgram { dfa_cache: Arc::new(Mutex::new(DfaCache::new(&program))), program })
+        };
+
+        RegexSet { regexes, combined, program }
+    }
+
+    /// The regexes in the set, in the order they were given.
+    pub fn regexes(&self) -> &[Regex] {
+        &self.regexes
+    }
+
+    pub fn len(&self) -> usize {
+        self.regexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty()
+    }
+
+    /// Returns true if any regex in the set matches anywhere in `haystack`.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.matches(haystack).matched_any()
+    }
+
+    /// Which regexes match anywhere in `haystack`.
+    pub fn matches(&self, haystack: &str) -> SetMatches {
+        self.matches_bytes(haystack.as_bytes())
+    }
+
+    /// Like [`RegexSet::matches`] for a haystack that doesn't have to be valid UTF-8. A regex whose
+    /// search runs out of its budget counts as not matching.
+    pub fn matches_bytes(&self, haystack: &[u8]) -> SetMatches {
+        // can't fail, is_match_bytes already counts running out of budget as no match
+        self.matches_with(haystack, |regex| Ok(regex.is_match_bytes(haystack))).unwrap()
+    }
+
+    /// Like [`RegexSet::matches_bytes`], but fails if the search of a regex with backreferences
+    /// ran out of its budget.
+    pub fn try_matches_bytes(&self, haystack: &[u8]) -> Result<SetMatches, Error> {
+        self.matches_with(haystack, |regex| regex.try_is_match_bytes(haystack))
+    }
+
+    // is_match is only called for the regexes that aren't part of the combined program
+    fn matches_with(&self, haystack: &[u8], mut is_match: impl FnMut(&Regex) -> Result<bool, Error>) -> Result<SetMatches, Error> {
+        let mut matched = vec![false; self.regexes.len()];
+
+        // the combined program runs on the lazy DFA, unless no regex can match judging by its literals
+        let combined_program = self.program.as_ref().filter(|_| self.combined.iter().any(|&index| self.regexes[index].may_match(haystack)));
+        if let Some(CombinedProgram { program, dfa_cache }) = combined_program {
+            let mut combined_matched = vec![false; self.combined.len()];
+            DfaCache::with_shared(dfa_cache, program, DfaCache::new, |cache| cache.which_match(program, haystack, &mut combined_matched));
+            for (pattern_id, &index) in self.combined.iter().enumerate() {
+                matched[index] = combined_matched[pattern_id];
+            }
+        }
+
+        for (index, regex) in self.regexes.iter().enumerate() {
+            if !regex.uses_dfa() {
+                matched[index] = is_match(regex)?;
+            }
+        }
+
+        Ok(SetMatches { matched })
+    }
+}
+
+
+/// Which regexes of a [`RegexSet`] matched, created by [`RegexSet::matches`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetMatches {
+    matched: Vec<bool>,
+}
+
+
+impl SetMatches {
+    pub fn matched_any(&self) -> bool {
+        self.matched.contains(&true)
+    }
+
+    /// Whether the regex at `index` matched.
+    pub fn matched(&self, index: usize) -> bool {
+        self.matched[index]
+    }
+
+    /// The indices of the regexes that matched, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.matched.len()).filter(|&index| self.matched[index])
+    }
+
+    /// Number of regexes in the set, matched or not.
+    pub fn len(&self) -> usize {
+        self.matched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matched.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_backreference_patterns() {
+        let set = RegexSet::new([r"(\w+) \1", r"(a)\1"]).unwrap();
+        assert_eq!(set.matches("hello hello").iter().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(set.matches("aa aa").iter().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(!set.is_match("hello world"));
+    }
+
+    #[test]
+    fn mixed_patterns() {
+        let set = RegexSet::new([r"(\w+) \1", "world"]).unwrap();
+        assert_eq!(set.matches("hello world").iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(set.matches("world world").iter().collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn empty_set() {
+        let set = RegexSet::new(Vec::<&str>::new()).unwrap();
+        assert!(set.is_empty());
+        assert!(!set.is_match("anything"));
+        assert!(set.matches("").is_empty());
+    }
+}
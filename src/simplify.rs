@@ -0,0 +1,325 @@
+use crate::pattern::{RegexAst, Repetition};
+
+
+// Rewrites the tree produced by pattern_to_ast into an equivalent but smaller one:
+// - nested concatenations / alternations are flattened
+// - runs of literals are merged into a single LiteralString
+// - common literal prefixes of neighbouring alternatives are factored out (foo|far -> f(?:oo|ar))
+// - nested repetitions of a non-capturing node are collapsed ((?:a*)* -> a*)
+// - alternations of single characters become a character class (a|b|c -> [abc])
+// The rewritten tree always matches exactly the same strings as the original.
+pub fn simplify(ast: RegexAst) -> RegexAst {
+    match ast {
+        RegexAst::Concat(parts) => simplify_concat(parts.into_iter().map(simplify).collect()),
+        RegexAst::Alternate(branches) => simplify_alternate(branches.into_iter().map(simplify).collect()),
+        RegexAst::Repeat(node, repetition) => simplify_repeat(simplify(*node), repetition),
+        RegexAst::CaptureGroup(group_id, name, node) => RegexAst::CaptureGroup(group_id, name, Box::new(simplify(*node))),
+        other => other,
+    }
+}
+
+
+fn simplify_concat(parts: Vec<RegexAst>) -> RegexAst {
+    let mut flattened = vec![];
+    for part in parts {
+        match part {
+            RegexAst::Concat(inner) => flattened.extend(inner),
+            part => flattened.push(part),
+        }
+    }
+
+    // merge neighbouring literals into one string literal
+    let mut merged: Vec<RegexAst> = vec![];
+    for part in flattened {
+        let text = match &part {
+            RegexAst::Literal(c) => c.to_string(),
+            RegexAst::LiteralString(text) => text.clone(),
+            _ => {
+                merged.push(part);
+                continue;
+            }
+        };
+
+        match merged.pop() {
+            Some(RegexAst::Literal(c)) => merged.push(RegexAst::LiteralString(format!("{}{}", c, text))),
+            Some(RegexAst::LiteralString(prev)) => merged.push(RegexAst::LiteralString(prev + &text)),
+            Some(prev) => {
+                merged.push(prev);
+                merged.push(part);
+            }
+            None => merged.push(part),
+        }
+    }
+
+    if merged.len() == 1 {
+        merged.into_iter().next().unwrap()
+    } else {
+        RegexAst::Concat(merged)
+    }
+}
+
+
+fn simplify_alternate(branches: Vec<RegexAst>) -> RegexAst {
+    let mut flattened = vec![];
+    for branch in branches {
+        match branch {
+            RegexAst::Alternate(inner) => flattened.extend(inner),
+            branch => flattened.push(branch),
+        }
+    }
+
+    // a|b|[cd] -> [abcd], only single characters are involved so the order doesn't matter
+    if flattened.iter().all(|branch| matches!(branch, RegexAst::Literal(_) | RegexAst::PositiveGroup(_))) {
+        let mut group = String::new();
+        for branch in &flattened {
+            let chars = match branch {
+                RegexAst::Literal(c) => c.to_string(),
+                RegexAst::PositiveGroup(chars) => chars.clone(),
+                _ => unreachable!(),
+            };
+            for c in chars.chars() {
+                if !group.contains(c) {
+                    group.push(c);
+                }
+            }
+        }
+        return RegexAst::PositiveGroup(group);
+    }
+
+    // Factor out common prefixes, only between neighbouring branches so that
+    // the order in which the alternatives are tried stays the same
+    let mut result: Vec<RegexAst> = vec![];
+    let mut ind = 0;
+    while ind < flattened.len() {
+        let first_char = literal_prefix(&flattened[ind]).chars().next();
+
+        let mut run_end = ind + 1;
+        if first_char.is_some() {
+            while run_end < flattened.len() && literal_prefix(&flattened[run_end]).chars().next() == first_char {
+                run_end += 1;
+            }
+        }
+
+        if run_end - ind == 1 {
+            result.push(flattened[ind].clone());
+            ind += 1;
+            continue;
+        }
+
+        let run = &flattened[ind..run_end];
+        let prefix = run.iter().map(literal_prefix).reduce(|common, prefix| common_prefix(&common, &prefix)).unwrap();
+        let prefix_len = prefix.chars().count();
+
+        let remainders = run.iter().map(|branch| strip_literal_prefix(branch.clone(), prefix_len)).collect();
+        result.push(simplify_concat(vec![literal_node(prefix), simplify_alternate(remainders)]));
+        ind = run_end;
+    }
+
+    if result.len() == 1 {
+        result.into_iter().next().unwrap()
+    } else {
+        RegexAst::Alternate(result)
+    }
+}
+
+
+fn simplify_repeat(node: RegexAst, repetition: Repetition) -> RegexAst {
+    if repetition == Repetition::None {
+        return node;
+    }
+
+    // Only a non-capturing inner repeat can be merged, a group has to stay inside the repeat that decides
+    // which repetition it reports.
+    // Mixing greedy and lazy would change which match is preferred, so those stay nested.
+    match node {
+        RegexAst::Repeat(inner, inner_repetition) if inner_repetition.is_lazy() == repetition.is_lazy() => {
+            let combined = match (inner_repetition, repetition) {
+                (Repetition::Plus, Repetition::Plus) => Repetition::Plus,
+                (Repetition::Optional, Repetition::Optional) => Repetition::Optional,
+                (Repetition::LazyPlus, Repetition::LazyPlus) => Repetition::LazyPlus,
+                (Repetition::LazyOptional, Repetition::LazyOptional) => Repetition::LazyOptional,
+                _ if repetition.is_lazy() => Repetition::LazyStar,
+                _ => Repetition::Star,
+            };
+            RegexAst::Repeat(inner, combined)
+        }
+        node => RegexAst::Repeat(Box::new(node), repetition),
+    }
+}
+
+
+// The literal text every match of this (simplified) node starts with
+fn literal_prefix(node: &RegexAst) -> String {
+    match node {
+        RegexAst::Literal(c) => c.to_string(),
+        RegexAst::LiteralString(text) => text.clone(),
+        RegexAst::Concat(parts) => parts.first().map(literal_prefix).unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+
+fn common_prefix(a: &str, b: &str) -> String {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).map(|(x, _)| x).collect()
+}
+
+
+fn literal_node(text: String) -> RegexAst {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => RegexAst::Literal(c),
+        _ => RegexAst::LiteralString(text),
+    }
+}
+
+
+// Removes the first `count` characters of the literal prefix of the node
+fn strip_literal_prefix(node: RegexAst, count: usize) -> RegexAst {
+    match node {
+        RegexAst::Literal(_) => RegexAst::Concat(vec![]),
+        RegexAst::LiteralString(text) => {
+            let rest: String = text.chars().skip(count).collect();
+            if rest.is_empty() {
+                RegexAst::Concat(vec![])
+            } else {
+                literal_node(rest)
+            }
+        }
+        RegexAst::Concat(mut parts) => {
+            let first = strip_literal_prefix(parts.remove(0), count);
+            parts.insert(0, first);
+            simplify_concat(parts)
+        }
+        _ => unreachable!("node has no literal prefix"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{solve, Budget, CaptureMap, MatchPath};
+    use crate::utils::pattern_to_ast;
+
+    fn simplified(pattern: &str) -> RegexAst {
+        simplify(pattern_to_ast(pattern).unwrap())
+    }
+
+    fn literal_string(text: &str) -> RegexAst {
+        RegexAst::LiteralString(text.to_string())
+    }
+
+    // The leftmost-first and the leftmost-longest match of the node as (start, end, captures)
+    fn leftmost_matches(node: &RegexAst, haystack: &str) -> Option<((usize, MatchPath), (usize, MatchPath))> {
+        let haystack = haystack.as_bytes();
+        (0..=haystack.len()).find_map(|start| {
+            let paths = solve(haystack, node, start, &CaptureMap::new(), &mut Budget::new(usize::MAX, usize::MAX)).unwrap();
+            let longest = paths.iter().map(|(end, _)| *end).max()?;
+            let first = paths[0].clone();
+            let longest = paths.into_iter().find(|(end, _)| *end == longest).unwrap();
+            Some(((start, first), (start, longest)))
+        })
+    }
+
+    #[test]
+    fn flattens_concatenations() {
+        assert_eq!(
+            simplify(RegexAst::Concat(vec![
+                RegexAst::Concat(vec![RegexAst::Digit, RegexAst::Concat(vec![RegexAst::Word])]),
+                RegexAst::Wildcard(b'\n'),
+            ])),
+            RegexAst::Concat(vec![RegexAst::Digit, RegexAst::Word, RegexAst::Wildcard(b'\n')])
+        );
+        assert_eq!(simplify(RegexAst::Concat(vec![RegexAst::Concat(vec![RegexAst::Digit])])), RegexAst::Digit);
+    }
+
+    #[test]
+    fn flattens_alternations() {
+        assert_eq!(
+            simplify(RegexAst::Alternate(vec![
+                RegexAst::Alternate(vec![literal_string("cat"), RegexAst::Digit]),
+                literal_string("dog"),
+            ])),
+            RegexAst::Alternate(vec![literal_string("cat"), RegexAst::Digit, literal_string("dog")])
+        );
+    }
+
+    #[test]
+    fn merges_literals() {
+        assert_eq!(simplified("abc"), literal_string("abc"));
+        assert_eq!(
+            simplified(r"ab\dcd"),
+            RegexAst::Concat(vec![literal_string("ab"), RegexAst::Digit, literal_string("cd")])
+        );
+        assert_eq!(simplified("a"), RegexAst::Literal('a'));
+    }
+
+    #[test]
+    fn factors_out_common_prefixes() {
+        assert_eq!(
+            simplified("foo|far"),
+            RegexAst::Concat(vec![
+                RegexAst::Literal('f'),
+                RegexAst::Alternate(vec![literal_string("oo"), literal_string("ar")]),
+            ])
+        );
+        // only neighbouring branches, bar stays between the two f branches
+        assert_eq!(
+            simplified("foo|bar|far"),
+            RegexAst::Alternate(vec![literal_string("foo"), literal_string("bar"), literal_string("far")])
+        );
+    }
+
+    #[test]
+    fn collapses_nested_repeats() {
+        assert_eq!(simplified("(?:a*)*"), RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::Star));
+        assert_eq!(simplified("(?:a+)+"), RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::Plus));
+        assert_eq!(simplified("(?:a?)*"), RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::Star));
+        assert_eq!(simplified("(?:a*?)+?"), RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::LazyStar));
+        // greedy inside lazy changes which match is preferred
+        assert_eq!(
+            simplified("(?:a*)*?"),
+            RegexAst::Repeat(Box::new(RegexAst::Repeat(Box::new(RegexAst::Literal('a')), Repetition::Star)), Repetition::LazyStar)
+        );
+        // the group has to stay, see simplify_repeat
+        assert!(matches!(simplified("(a*)*"), RegexAst::Repeat(node, Repetition::Star) if matches!(*node, RegexAst::CaptureGroup(..))));
+    }
+
+    #[test]
+    fn single_char_alternation_becomes_class() {
+        assert_eq!(simplified("a|b|c"), RegexAst::PositiveGroup("abc".to_string()));
+        assert_eq!(simplified("a|[bc]|a"), RegexAst::PositiveGroup("abc".to_string()));
+    }
+
+    #[test]
+    fn simplified_tree_matches_like_the_original() {
+        let cases = [
+            ("foo|far", vec!["foo", "far", "fa", "xxfarfoo", ""]),
+            ("(foo|far)(b)", vec!["farb", "foob", "fb"]),
+            ("(?:a*)*b", vec!["aaab", "b", "aaa", "xab"]),
+            ("(?:a+)+?", vec!["aaa", "", "baa"]),
+            ("(?:a?)*", vec!["aaa", "", "b"]),
+            (r"a|b|c|\d", vec!["xc", "x7", "x"]),
+            ("(a|b|c)+", vec!["abcd", "dcba", "d"]),
+            (r"(ab|ac)\1", vec!["abab", "acac", "abac"]),
+            (r"(x(?:y|yz))z\1", vec!["xyzzxyz", "xyzxy", "xyzzxy"]),
+            ("abc|abd|x", vec!["abd", "abc", "zx", "ab"]),
+            ("^(?:ab)+$", vec!["abab", "aba", ""]),
+            ("(a*)*", vec!["aaa", "", "b"]),
+        ];
+
+        for (pattern, haystacks) in cases {
+            let original = pattern_to_ast(pattern).unwrap();
+            let simplified = simplify(original.clone());
+            for haystack in haystacks {
+                assert_eq!(
+                    leftmost_matches(&original, haystack),
+                    leftmost_matches(&simplified, haystack),
+                    "{} on {:?}",
+                    pattern,
+                    haystack
+                );
+            }
+        }
+    }
+}
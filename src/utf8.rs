@@ -0,0 +1,57 @@
+use std::str;
+
+
+// What the matchers step over: a char, or a single byte that doesn't start valid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+
+// Decodes the unit starting at haystack[pos] and returns it with its length in bytes,
+// None at the end of the haystack
+pub fn decode(haystack: &[u8], pos: usize) -> Option<(Unit, usize)> {
+    let first = *haystack.get(pos)?;
+    if first.is_ascii() {
+        return Some((Unit::Char(first as char), 1));
+    }
+
+    // a char is at most 4 bytes, whatever comes after it doesn't matter
+    let bytes = &haystack[pos..haystack.len().min(pos + 4)];
+    let valid = match str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => str::from_utf8(&bytes[..err.valid_up_to()]).unwrap(),
+    };
+
+    match valid.chars().next() {
+        Some(c) => Some((Unit::Char(c), c.len_utf8())),
+        None => Some((Unit::Byte(first), 1)),
+    }
+}
+
+
+// Length in bytes of the unit at haystack[pos], 1 at the end so a search can step past it
+pub fn unit_len(haystack: &[u8], pos: usize) -> usize {
+    decode(haystack, pos).map_or(1, |(_, len)| len)
+}
+
+
+// Decodes the unit that ends at haystack[end], None at the start of the haystack. Going backwards from
+// a unit boundary this splits the bytes into the same units decode() does going forwards.
+pub fn decode_last(haystack: &[u8], end: usize) -> Option<(Unit, usize)> {
+    let last = *haystack.get(end.checked_sub(1)?)?;
+    if last.is_ascii() {
+        return Some((Unit::Char(last as char), 1));
+    }
+
+    // only a char starting at a lead byte can end here, continuation bytes never start one
+    for len in 2..=end.min(4) {
+        if let Some((unit @ Unit::Char(_), char_len)) = decode(haystack, end - len) {
+            if char_len == len {
+                return Some((unit, len));
+            }
+        }
+    }
+    Some((Unit::Byte(last), 1))
+}
@@ -1,98 +1,158 @@
 use core::panic;
-use std::fs;
 
+use crate::error::Error;
 use crate::pattern::{RegexAst, Repetition};
+use crate::utf8::Unit;
 
 
+// Settings that change what parts of the pattern mean
+#[derive(Debug, Clone, Copy)]
+pub struct ParseFlags {
+    pub dot_all: bool,        // . matches the line terminator too, (?s) turns it on for the rest of its group
+    pub multi_line: bool,     // ^ and $ match at the start and end of every line, not just of the input
+    pub line_terminator: u8,  // what ends a line for ., multi-line ^ and $, an ASCII byte
+}
+
+
+impl Default for ParseFlags {
+    fn default() -> ParseFlags {
+        ParseFlags { dot_all: false, multi_line: false, line_terminator: b'\n' }
+    }
+}
+
 
+// Parses the pattern into an AST, positions in errors are char indices into the pattern
+pub fn pattern_to_ast(pattern: &str) -> Result<RegexAst, Error> {
+    pattern_to_ast_with(pattern, ParseFlags::default())
+}
 
 
-pub fn pattern_to_ast(pattern: &str) -> RegexAst {
+pub fn pattern_to_ast_with(pattern: &str, mut flags: ParseFlags) -> Result<RegexAst, Error> {
     let mut pattern_ind = 0;
     let mut group_counter = 0;
 
-    parse_alternation(pattern, &mut pattern_ind, &mut group_counter)
+    let ast = parse_alternation(pattern, &mut pattern_ind, &mut group_counter, &mut flags)?;
+
+    // parse_alternation only stops early on a ')' that doesn't close any group
+    if peek(pattern, pattern_ind).is_some() {
+        return Err(Error::UnmatchedParen(pattern_ind));
+    }
+
+    Ok(ast)
+}
+
+
+fn peek(pattern: &str, pattern_ind: usize) -> Option<char> {
+    pattern.chars().nth(pattern_ind)
 }
 
 
-fn parse_alternation(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -> RegexAst {
-    let mut branches = vec![parse_concatination(pattern, pattern_ind, group_counter)];
+// dot_all is set by (?s) from there to the end of the group it's in, so the flags are restored on the way out
+fn parse_alternation(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32, flags: &mut ParseFlags) -> Result<RegexAst, Error> {
+    let outer_flags = *flags;
+    let mut branches = vec![parse_concatination(pattern, pattern_ind, group_counter, flags)?];
     
-    while *pattern_ind < pattern.len() && pattern.chars().nth(*pattern_ind).unwrap() == '|' {
+    while peek(pattern, *pattern_ind) == Some('|') {
         *pattern_ind += 1;
-        branches.push(parse_concatination(pattern, pattern_ind, group_counter));
+        branches.push(parse_concatination(pattern, pattern_ind, group_counter, flags)?);
     }
     
+    *flags = outer_flags;
+
     // Only create Alternate if there are multiple branches
     if branches.len() == 1 {
-        branches.into_iter().next().unwrap()
+        Ok(branches.into_iter().next().unwrap())
     } else {
-        RegexAst::Alternate(branches)
+        Ok(RegexAst::Alternate(branches))
     }
 }
 
-fn parse_concatination(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -> RegexAst {
+fn parse_concatination(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32, flags: &mut ParseFlags) -> Result<RegexAst, Error> {
     let mut parts = vec![];
     
-    while *pattern_ind < pattern.len() {
-        let c = pattern.chars().nth(*pattern_ind).unwrap();
+    while let Some(c) = peek(pattern, *pattern_ind) {
         if c == ')' || c == '|' {
             break;
         }
-        parts.push(parse_repeat(pattern, pattern_ind, group_counter));
+        // (?s): from here on . matches '\n' too
+        if pattern.chars().skip(*pattern_ind).take(4).eq("(?s)".chars()) {
+            *pattern_ind += 4;
+            flags.dot_all = true;
+            continue;
+        }
+        parts.push(parse_repeat(pattern, pattern_ind, group_counter, flags)?);
     }
     
     // Only create Concat if there are multiple parts
     if parts.len() == 1 {
-        parts.into_iter().next().unwrap()
+        Ok(parts.into_iter().next().unwrap())
     } else {
-        RegexAst::Concat(parts)
+        Ok(RegexAst::Concat(parts))
     }
 }
 
 
 
-fn parse_repeat(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -> RegexAst {
-    let node = parse_atom(pattern, pattern_ind, group_counter);
+fn parse_repeat(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32, flags: &mut ParseFlags) -> Result<RegexAst, Error> {
+    let node = parse_atom(pattern, pattern_ind, group_counter, flags)?;
     let rep = get_repition_type(pattern, pattern_ind);
     if rep == Repetition::None {
-        node
+        Ok(node)
     } else {
-        RegexAst::Repeat(Box::new(node), rep)
+        Ok(RegexAst::Repeat(Box::new(node), rep))
     }
 }
 
-fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -> RegexAst {
-    match pattern.chars().nth(*pattern_ind).unwrap() {
+// Only called when there is at least one char left in the pattern
+fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32, flags: &mut ParseFlags) -> Result<RegexAst, Error> {
+    let atom_start = *pattern_ind;
+
+    let node = match peek(pattern, *pattern_ind).unwrap() {
         '^' => {
             *pattern_ind += 1;
-            RegexAst::StartOfLine
+            if flags.multi_line { RegexAst::LineStart(flags.line_terminator) } else { RegexAst::StartOfLine }
         },
 
         '$' => {
             *pattern_ind += 1;
-            RegexAst::EndOfLine
+            if flags.multi_line { RegexAst::LineEnd(flags.line_terminator) } else { RegexAst::EndOfLine }
         }, 
 
         '.' => {
             *pattern_ind += 1;
-            RegexAst::Wildcard
+            if flags.dot_all { RegexAst::AnyChar } else { RegexAst::Wildcard(flags.line_terminator) }
         },
 
 
+        '(' if pattern.chars().skip(*pattern_ind).take(3).eq("(?:".chars()) => {
+            // non-capturing group, only used for grouping so no CaptureGroup node
+            *pattern_ind += 3;
+            let node = parse_alternation(pattern, pattern_ind, group_counter, flags)?;
+
+            if peek(pattern, *pattern_ind) != Some(')') {
+                return Err(Error::UnclosedGroup(atom_start));
+            }
+
+            *pattern_ind += 1;
+
+            node
+        }
+
+
         '(' => {
             *pattern_ind += 1;
             *group_counter += 1;
             let group_id = *group_counter;
-            let node = parse_alternation(pattern, pattern_ind, group_counter);
+            let name = parse_group_name(pattern, pattern_ind)?;
+            let node = parse_alternation(pattern, pattern_ind, group_counter, flags)?;
 
-            if *pattern_ind >= pattern.len() || pattern.chars().nth(*pattern_ind).unwrap() != ')' {
-                panic!("involid pattern, ( is not closed")
+            if peek(pattern, *pattern_ind) != Some(')') {
+                return Err(Error::UnclosedGroup(atom_start));
             }
 
             *pattern_ind += 1;
 
-            RegexAst::CaptureGroup(group_id, Box::new(node))
+            RegexAst::CaptureGroup(group_id, name, Box::new(node))
         }
 
 
@@ -100,21 +160,19 @@ fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -
             *pattern_ind += 1;
             let mut negative_group = false;
 
-            if pattern.chars().nth(*pattern_ind).unwrap() == '^' {
+            if peek(pattern, *pattern_ind) == Some('^') {
                 *pattern_ind += 1;
                 negative_group = true;
             }
 
-            let closing_bracket_ind = pattern[*pattern_ind..].find(']');
+            let group: String = pattern.chars().skip(*pattern_ind).take_while(|c| *c != ']').collect();
+            *pattern_ind += group.chars().count();
 
-            if closing_bracket_ind.is_none() {
-                panic!("Unhandled pattern: {pattern}");
+            if peek(pattern, *pattern_ind) != Some(']') {
+                return Err(Error::UnclosedClass(atom_start));
             }
 
-            let closing_bracket_ind = closing_bracket_ind.unwrap() + *pattern_ind;
-            let group = pattern[*pattern_ind..closing_bracket_ind].to_string();
-            
-            *pattern_ind = closing_bracket_ind + 1;
+            *pattern_ind += 1;
 
             match negative_group {
                 true => RegexAst::NegativeGroup(group),
@@ -124,7 +182,7 @@ fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -
 
         '\\' => {
             *pattern_ind += 1;
-            match pattern.chars().nth(*pattern_ind).unwrap() {
+            match peek(pattern, *pattern_ind).ok_or(Error::TrailingBackslash)? {
                 'w' => {
                     *pattern_ind += 1;
                     RegexAst::Word
@@ -133,11 +191,36 @@ fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -
                     *pattern_ind += 1;
                     RegexAst::Digit
                 }
+                'n' => {
+                    *pattern_ind += 1;
+                    RegexAst::Literal('\n')
+                }
+                't' => {
+                    *pattern_ind += 1;
+                    RegexAst::Literal('\t')
+                }
+                'r' => {
+                    *pattern_ind += 1;
+                    RegexAst::Literal('\r')
+                }
                 c if c.is_ascii_digit() => {
                     *pattern_ind += 1;
                     let group_num = c.to_digit(10).unwrap();
                     RegexAst::Backreference(group_num)
                 }
+                'x' => {
+                    // \xNN, two hex digits
+                    let escape_start = *pattern_ind - 1;
+                    let hex: String = pattern.chars().skip(*pattern_ind + 1).take(2).collect();
+                    if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(Error::InvalidHexEscape(escape_start));
+                    }
+                    *pattern_ind += 3;
+
+                    // below 0x80 it's an ordinary char, above only a byte that isn't valid UTF-8 matches
+                    let byte = u8::from_str_radix(&hex, 16).unwrap();
+                    if byte.is_ascii() { RegexAst::Literal(byte as char) } else { RegexAst::Byte(byte) }
+                }
                 c => {
                     *pattern_ind += 1;
                     RegexAst::Literal(c)
@@ -149,27 +232,76 @@ fn parse_atom(pattern: &str, pattern_ind: &mut usize, group_counter: &mut u32) -
             *pattern_ind += 1;
             RegexAst::Literal(literal)
         }
-    }
+    };
+
+    Ok(node)
 }
 
 
 
 
+// Reads the ?P<name> / ?<name> part right after the '(' of a named group
+fn parse_group_name(pattern: &str, pattern_ind: &mut usize) -> Result<Option<String>, Error> {
+    let name_start = if pattern.chars().skip(*pattern_ind).take(3).eq("?P<".chars()) {
+        *pattern_ind + 3
+    } else if pattern.chars().skip(*pattern_ind).take(2).eq("?<".chars()) {
+        *pattern_ind + 2
+    } else {
+        return Ok(None);
+    };
+
+    let name: String = pattern.chars().skip(name_start).take_while(|c| *c != '>').collect();
+    let name_end = name_start + name.chars().count();
+
+    let valid_name = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if peek(pattern, name_end) != Some('>') || !valid_name {
+        return Err(Error::InvalidGroupName(*pattern_ind));
+    }
+
+    *pattern_ind = name_end + 1;
+    Ok(Some(name))
+}
+
+
 fn get_repition_type(pattern: &str, last_index_in_pattern: &mut usize) -> Repetition {
-    if *last_index_in_pattern >= pattern.len() {
-        return Repetition::None;
+    let greedy = match peek(pattern, *last_index_in_pattern) {
+        Some('*') => { *last_index_in_pattern += 1; Repetition::Star }
+        Some('+') => { *last_index_in_pattern += 1; Repetition::Plus }
+        Some('?') => { *last_index_in_pattern += 1; Repetition::Optional }
+        _ => return Repetition::None,
+    };
+
+    // a trailing '?' makes the repetition lazy
+    if peek(pattern, *last_index_in_pattern) != Some('?') {
+        return greedy;
     }
 
-    match pattern.chars().nth(*last_index_in_pattern).unwrap() {
-        '*' => { *last_index_in_pattern += 1; Repetition::Star }
-        '+' => { *last_index_in_pattern += 1; Repetition::Plus }
-        '?' => { *last_index_in_pattern += 1; Repetition::Optional }
-        _ => Repetition::None,
+    *last_index_in_pattern += 1;
+    match greedy {
+        Repetition::Star => Repetition::LazyStar,
+        Repetition::Plus => Repetition::LazyPlus,
+        _ => Repetition::LazyOptional,
     }
 }
 
 
 
+// The pattern that matches exactly `text`, every char with a meaning of its own gets a backslash
+pub fn escape(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.^$*+?()[]|{}".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+
 pub fn match_pattern_with_char(pattern: &RegexAst, c: char) -> bool {
     match pattern {
         RegexAst::Digit => c.is_ascii_digit(),
@@ -182,8 +314,38 @@ pub fn match_pattern_with_char(pattern: &RegexAst, c: char) -> bool {
 
         RegexAst::Literal(l) => *l == c,
 
-        RegexAst::Wildcard => true,
+        RegexAst::Wildcard(terminator) => c != *terminator as char,
+
+        RegexAst::AnyChar => true,
+
+        // only matches bytes that aren't valid UTF-8, see match_unit
+        RegexAst::Byte(_) => false,
         
         _ => panic!("Code should not reach here ")
     }
+}
+
+
+// Whether the multi-line ^ (RegexAst::LineStart) holds at the byte offset `pos`
+pub(crate) fn is_line_start(haystack: &[u8], pos: usize, terminator: u8) -> bool {
+    pos == 0 || haystack[pos - 1] == terminator
+}
+
+
+// Whether the multi-line $ (RegexAst::LineEnd) holds at the byte offset `pos`
+pub(crate) fn is_line_end(haystack: &[u8], pos: usize, terminator: u8) -> bool {
+    pos == haystack.len() || haystack[pos] == terminator
+}
+
+
+// Like match_pattern_with_char, but a byte that isn't valid UTF-8 is only matched by . and \xNN
+pub(crate) fn match_unit(pattern: &RegexAst, unit: Unit) -> bool {
+    match unit {
+        Unit::Char(c) => match_pattern_with_char(pattern, c),
+        Unit::Byte(byte) => match pattern {
+            RegexAst::Wildcard(_) | RegexAst::AnyChar => true,
+            RegexAst::Byte(b) => *b == byte,
+            _ => false,
+        },
+    }
 }
\ No newline at end of file
@@ -0,0 +1,193 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+
+// Runs the binary with `args` on `input` as stdin
+fn run(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-grep"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+
+// The exit code, stdout and stderr of a run
+fn grep_with_stderr(args: &[&str], input: &[u8]) -> (i32, String, String) {
+    let output = run(args, input);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output.status.code().unwrap(), stdout, stderr)
+}
+
+
+fn grep(args: &[&str], input: &[u8]) -> (i32, String) {
+    let (code, stdout, _) = grep_with_stderr(args, input);
+    (code, stdout)
+}
+
+
+#[test]
+fn only_matching_prints_matches_of_every_pattern_in_order() {
+    assert_eq!(grep(&["-o", "-E", "a", "-e", "b"], b"b a\n"), (0, "b\na\n".to_string()));
+}
+
+
+#[test]
+fn backreference_patterns_on_their_own() {
+    assert_eq!(grep(&["-E", r"(\w+) \1"], b"hello hello\nhello world\n"), (0, "hello hello\n".to_string()));
+}
+
+
+#[test]
+fn multiline_anchors_match_at_line_boundaries() {
+    assert_eq!(grep(&["-U", "-E", "c$"], b"abc\ndef\n"), (0, "1:abc\n".to_string()));
+    assert_eq!(grep(&["-U", "-E", "^def"], b"abc\ndef\n"), (0, "2:def\n".to_string()));
+    assert_eq!(grep(&["-U", "-E", r"^(ab)\n\1$"], b"ab\nab\n"), (0, "1:ab\nab\n".to_string()));
+    assert_eq!(grep(&["-U", "-E", "b$"], b"abc\ndef\n"), (1, String::new()));
+}
+
+
+#[test]
+fn null_data_dot_matches_newline() {
+    assert_eq!(grep(&["-z", "-E", "a.b"], b"a\nb\0axb\0ab\0"), (0, "a\nb\0axb\0".to_string()));
+    assert_eq!(grep(&["-E", "a.b"], b"a\nb\n"), (1, String::new()));
+}
+
+
+#[test]
+fn multiline_null_data_anchors_match_at_record_boundaries() {
+    assert_eq!(grep(&["-U", "-z", "-E", "^c"], b"a\nb\0c\0"), (0, "2:c\0".to_string()));
+    assert_eq!(grep(&["-U", "-z", "-E", "a$"], b"a\nb\0c\0"), (1, String::new()));
+    assert_eq!(grep(&["-U", "-z", "-E", "^b"], b"a\nb\0c\0"), (1, String::new()));
+    // . takes the '\n' inside a record but not the '\0' between two
+    assert_eq!(grep(&["-U", "-z", "-o", "-E", "a.b"], b"a\nb\0c\0"), (0, "a\nb\0".to_string()));
+    assert_eq!(grep(&["-U", "-z", "-E", "b.c"], b"a\nb\0c\0"), (1, String::new()));
+}
+
+
+#[test]
+fn backtrack_limit_exits_with_status_2() {
+    let input = format!("aaaab\n{}bc\n", "a".repeat(30));
+    let (code, stdout, stderr) = grep_with_stderr(&["--backtrack-limit", "1000", "-E", r"(a|aa)+\1c"], input.as_bytes());
+    assert_eq!((code, stdout.as_str()), (2, ""));
+    assert!(stderr.contains("line 2: backtracking gave up after 1000 steps"), "{}", stderr);
+}
+
+
+#[test]
+fn only_matching_keeps_invalid_bytes() {
+    let output = run(&["-o", "-E", "a.b"], b"xa\xffby\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"a\xffb\n");
+    let output = run(&["-o", "-E", r"\xfe+"], b"\xfe\xfe\xfex\n");
+    assert_eq!(output.stdout, b"\xfe\xfe\xfe\n");
+}
+
+
+#[test]
+fn explain_describes_the_pattern_as_written() {
+    // simplify() would factor out the common a and merge (?:a*)* into a*
+    assert_eq!(
+        grep(&["--explain", "ab|ac"], b""),
+        (0, "pattern: ab|ac\n  matches: either (literal 'a', then literal 'b') or (literal 'a', then literal 'c')\n".to_string())
+    );
+    assert_eq!(
+        grep(&["--explain", "(?:a*)*x"], b""),
+        (0, "pattern: (?:a*)*x\n  matches: zero or more repetitions of (zero or more 'a' characters), then literal 'x'\n".to_string())
+    );
+    assert_eq!(grep(&["--explain", "(a"], b""), (2, String::new()));
+}
+
+
+// Writes `contents` to a file of its own in the temp directory, removed again when dropped
+struct TempFile(std::path::PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &[u8]) -> TempFile {
+        let path = std::env::temp_dir().join(format!("codecrafters-grep-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+
+// More than the 1 MiB it takes for a file to be mapped: matching lines all over the file, CRLF line
+// endings, a line longer than a read chunk and a last line without a newline
+fn big_input() -> Vec<u8> {
+    let mut input = vec![];
+    for line in 0..40_000 {
+        if line % 997 == 0 {
+            input.extend_from_slice(format!("line {} has a needle in it\n", line).as_bytes());
+        } else if line % 1999 == 0 {
+            input.extend_from_slice(format!("line {} ends with a needle\r\n", line).as_bytes());
+        } else {
+            input.extend_from_slice(format!("line {} is just filler text\n", line).as_bytes());
+        }
+    }
+    input.extend_from_slice(&[b'x'; 100_000]);
+    input.extend_from_slice(b"needle\nthe last needle");
+    input
+}
+
+
+#[test]
+fn mapped_and_read_files_give_the_same_output() {
+    let input = big_input();
+    assert!(input.len() > 1024 * 1024);
+    let file = TempFile::new("mmap", &input);
+
+    for args in [&["-E", "needle"][..], &["-o", "-E", r"\d+ \w+ a needle"], &["-E", "needle$"], &["-U", "-E", r"needle in it\nline"]] {
+        let mapped = grep(&[args, &[file.path()]].concat(), b"");
+        let read = grep(&[args, &["--no-mmap", file.path()]].concat(), b"");
+        assert_eq!(mapped.0, 0, "{:?}", args);
+        assert_eq!(mapped, read, "{:?}", args);
+    }
+    // the '\r' of a CRLF line isn't printed and the last line gets a newline
+    let (_, stdout) = grep(&["-E", "needle$", file.path()], b"");
+    assert!(stdout.starts_with("line 1999 ends with a needle\n"), "{}", &stdout[..100]);
+    assert!(stdout.ends_with("xxxneedle\nthe last needle\n"));
+}
+
+
+#[test]
+fn stdin_gives_the_same_output_as_the_file() {
+    let input = big_input();
+    let file = TempFile::new("stdin", &input);
+
+    for args in [&["-E", "needle"][..], &["-o", "-E", r"\d+ \w+ a needle"], &["-U", "-E", r"needle in it\nline"]] {
+        let from_file = grep(&[args, &[file.path()]].concat(), b"");
+        assert_eq!(from_file.0, 0, "{:?}", args);
+        assert_eq!(grep(args, &input), from_file, "{:?}", args);
+        assert_eq!(grep(&[args, &["-"]].concat(), &input), from_file, "{:?}", args);
+    }
+}
+
+
+#[test]
+fn label_names_stdin_next_to_other_files() {
+    let file = TempFile::new("label", b"apple\nbanana\n");
+    let expected = format!("{}:apple\npiped:pineapple\n", file.path());
+    assert_eq!(grep(&["--label", "piped", "-E", "apple", file.path(), "-"], b"pineapple\ncherry\n"), (0, expected));
+
+    // without --label stdin is called like in grep
+    let (_, stdout) = grep(&["-E", "apple", "-", file.path()], b"pineapple\n");
+    assert!(stdout.starts_with("(standard input):pineapple\n"), "{}", stdout);
+    // a single input gets no prefix at all, label or not
+    assert_eq!(grep(&["--label", "piped", "-E", "apple"], b"pineapple\n"), (0, "pineapple\n".to_string()));
+    assert_eq!(grep(&["--label", "piped", "-E", "apple", "-"], b"pineapple\n"), (0, "pineapple\n".to_string()));
+}